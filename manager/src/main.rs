@@ -1,3 +1,4 @@
+use shared::config::manager_config::ManagerConfig;
 use shared::store::data_store::DataStore;
 use tracing::Level;
 
@@ -10,7 +11,8 @@ async fn main() {
         .with_thread_names(true)
         .init();
 
-    let data_store = DataStore::init();
+    let config = ManagerConfig::load();
+    let data_store = DataStore::init_with_config(&config);
 
     // start the manager server
     let data_store_for_server = data_store.clone();
@@ -1,4 +1,8 @@
-use shared::store::data_store::DataStore;
+use shared::config::manager_config::ManagerConfig;
+use shared::schemas::device_info::ProcessInfo;
+use shared::schemas::manager_messages::ProcessSortKey;
+use shared::server::manager_handle::ManagerHandle;
+use shared::store::data_store::{DataStore, DataStoreType};
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
@@ -6,20 +10,65 @@ fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+/// The top processes of a node, requested from the node on demand
+#[tauri::command]
+async fn get_processes(
+    manager: tauri::State<'_, ManagerHandle>,
+    ip: String,
+    limit: Option<usize>,
+    sort_by: Option<ProcessSortKey>,
+) -> Result<Vec<ProcessInfo>, String> {
+    let ip = ip.parse::<std::net::Ipv4Addr>().map_err(|e| e.to_string())?;
+    manager
+        .processes(ip, limit.unwrap_or(10), sort_by.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Ask a node for its machine info again
+#[tauri::command]
+async fn refresh_node(manager: tauri::State<'_, ManagerHandle>, ip: String) -> Result<(), String> {
+    let ip = ip.parse::<std::net::Ipv4Addr>().map_err(|e| e.to_string())?;
+    manager.refresh(ip).await.map_err(|e| e.to_string())
+}
+
+/// Broadcast the usage request now instead of waiting for the next poll
+#[tauri::command]
+async fn scan(manager: tauri::State<'_, ManagerHandle>) -> Result<(), String> {
+    manager.scan().await.map_err(|e| e.to_string())
+}
+
+/// Remove a node, returns whether it was known
+#[tauri::command]
+fn forget_node(data_store: tauri::State<'_, DataStoreType>, ip: String) -> Result<bool, String> {
+    let ip = ip.parse::<std::net::Ipv4Addr>().map_err(|e| e.to_string())?;
+    Ok(data_store.remove_node(&ip))
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 #[tokio::main]
 pub async fn run() {
 
-    let data_store = DataStore::init();
+    let config = ManagerConfig::load();
+    let data_store = DataStore::init_with_config(&config);
     let data_store_for_server = data_store.clone();
     let manager_server = shared::server::manager_server::ManagerServer::new(data_store_for_server);
+    let manager = manager_server.handle();
     tokio::spawn(async move {
         manager_server.run().await;
     });
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![greet])
+        .manage(manager)
+        .manage(data_store)
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            get_processes,
+            refresh_node,
+            scan,
+            forget_node
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -1,3 +1,4 @@
+use shared::config::node_config::NodeConfig;
 use tracing::Level;
 
 #[tokio::main]
@@ -8,7 +9,7 @@ async fn main() -> std::io::Result<()> {
         .with_thread_names(true)
         .init();
 
-    let target_server = shared::server::target_server::TargetServer::new();
+    let target_server = shared::server::target_server::TargetServer::new(NodeConfig::load());
 
     target_server.run().await.map_err(|e| {
         tracing::error!("Failed to run TargetServer: {}", e);
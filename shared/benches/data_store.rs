@@ -0,0 +1,94 @@
+//! Benchmarks of the data store with a fleet of 1,000 nodes.
+//!
+//! Run with `cargo bench -p shared --bench data_store`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use shared::schemas::device_info::MachineUsage;
+use shared::store::data_store::{DataStore, DataStoreType};
+use std::hint::black_box;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const NUMBER_OF_NODES: u32 = 1_000;
+const NUMBER_OF_RECORDS: usize = 500;
+
+fn usage() -> MachineUsage {
+    MachineUsage {
+        total_memory: 16 * 1024 * 1024 * 1024,
+        used_memory: 8 * 1024 * 1024 * 1024,
+        total_swap: 2 * 1024 * 1024 * 1024,
+        used_swap: 0,
+        cpu_usage: vec![12.5; 16],
+        cpu_frequency: vec![3_200; 16],
+        ..Default::default()
+    }
+}
+
+fn node_ip(index: u32) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 0)) + index)
+}
+
+/// A data store with every node holding a full usage queue
+fn populated_store() -> DataStoreType {
+    let data_store = DataStore::init();
+    let usage = usage();
+    for _ in 0..NUMBER_OF_RECORDS {
+        for index in 0..NUMBER_OF_NODES {
+            data_store.update_usage(node_ip(index), usage.clone());
+        }
+    }
+    data_store
+}
+
+/// Run the given benchmark while other threads keep updating the usage of every node.
+fn with_writers(data_store: &DataStoreType, writers: u32, bench: impl FnOnce()) {
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        for writer in 0..writers {
+            let stop = &stop;
+            scope.spawn(move || {
+                let usage = usage();
+                let mut index = writer;
+                while !stop.load(Ordering::Relaxed) {
+                    data_store.update_usage(node_ip(index % NUMBER_OF_NODES), usage.clone());
+                    index += writers;
+                }
+            });
+        }
+        bench();
+        stop.store(true, Ordering::Relaxed);
+    });
+}
+
+fn bench_data_store(c: &mut Criterion) {
+    let data_store = populated_store();
+    let usage = usage();
+
+    c.bench_function("update_usage/1000_nodes", |b| {
+        b.iter(|| {
+            for index in 0..NUMBER_OF_NODES {
+                data_store.update_usage(node_ip(index), black_box(usage.clone()));
+            }
+        })
+    });
+
+    c.bench_function("get_node/500_records", |b| {
+        b.iter(|| black_box(data_store.get_node(node_ip(42))))
+    });
+
+    c.bench_function("get_node_overview/1000_nodes", |b| {
+        b.iter(|| black_box(data_store.get_node_overview()))
+    });
+
+    with_writers(&data_store, 4, || {
+        c.bench_function("get_node/500_records/4_writers", |b| {
+            b.iter(|| black_box(data_store.get_node(node_ip(42))))
+        });
+        c.bench_function("get_node_overview/1000_nodes/4_writers", |b| {
+            b.iter(|| black_box(data_store.get_node_overview()))
+        });
+    });
+}
+
+criterion_group!(benches, bench_data_store);
+criterion_main!(benches);
@@ -1,5 +1,13 @@
+use crate::schemas::manager_messages::ProcessSortKey;
 use std::net::Ipv4Addr;
 
 pub enum DiscoveryCommand {
     DeviceInformation(Ipv4Addr),
+    /// Broadcast the usage request now instead of waiting for the next poll
+    Scan,
+    Processes {
+        ip: Ipv4Addr,
+        limit: usize,
+        sort_by: ProcessSortKey,
+    },
 }
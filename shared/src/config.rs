@@ -0,0 +1,30 @@
+//! The configuration of the binaries.
+//!
+//! Each configuration is read from a JSON file whose path can be changed with an environment
+//! variable. A missing file falls back to the default configuration.
+
+pub mod manager_config;
+pub mod node_config;
+pub mod web_server_config;
+
+use serde::de::DeserializeOwned;
+use tracing::info;
+
+/// Read the configuration from the file given by the environment variable or the default path.
+///
+/// # Panics
+///
+/// Panics if the file exists but cannot be read or parsed, a broken configuration should not be
+/// silently replaced by the default one.
+pub(crate) fn load<T: DeserializeOwned + Default>(path_env: &str, default_path: &str) -> T {
+    let path = std::env::var(path_env).unwrap_or_else(|_| default_path.to_string());
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .unwrap_or_else(|e| panic!("Failed to parse the config file {path}: {e}")),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("No config file found at {}, using the defaults", path);
+            T::default()
+        }
+        Err(e) => panic!("Failed to read the config file {path}: {e}"),
+    }
+}
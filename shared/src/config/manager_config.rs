@@ -0,0 +1,22 @@
+//! The configuration of the manager, shared by every front end that runs a `ManagerServer`.
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+const CONFIG_PATH_ENV: &str = "MANAGER_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "manager_config.json";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ManagerConfig {
+    /// The file the user-assigned labels and groups are persisted to.
+    /// Unset by default, then they are only kept in memory.
+    pub label_file: Option<PathBuf>,
+}
+
+impl ManagerConfig {
+    /// Load the configuration from `$MANAGER_CONFIG` or `manager_config.json`
+    pub fn load() -> Self {
+        crate::config::load(CONFIG_PATH_ENV, DEFAULT_CONFIG_PATH)
+    }
+}
@@ -0,0 +1,179 @@
+//! The configuration of a node, read by the `TargetServer`.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+const CONFIG_PATH_ENV: &str = "NODE_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "node_config.json";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NodeConfig {
+    /// Labels the node declares for itself, e.g. `env=prod` or `owner=storage-team`.
+    /// Labels assigned by a manager take precedence over these.
+    pub labels: BTreeMap<String, String>,
+    /// A free text description of the node
+    pub description: Option<String>,
+    pub network: NetworkConfig,
+    pub metrics: MetricsConfig,
+    /// Seconds between two samples of the usage, requests are answered with the latest sample
+    pub sample_interval: u64,
+    pub access: AccessConfig,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            labels: BTreeMap::new(),
+            description: None,
+            network: NetworkConfig::default(),
+            metrics: MetricsConfig::default(),
+            sample_interval: 5,
+            access: AccessConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NetworkConfig {
+    /// Skip the loopback and the other virtual interfaces such as bridges and veth pairs
+    pub exclude_virtual: bool,
+    /// Skip the interfaces whose names start with one of these prefixes, e.g. `docker` or `tun`
+    pub exclude: Vec<String>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            exclude_virtual: true,
+            exclude: Vec::new(),
+        }
+    }
+}
+
+/// Custom metrics in the Prometheus text format, reported with the usage of the node
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MetricsConfig {
+    /// A directory whose `*.prom` files are read on every usage request
+    pub textfile_directory: Option<PathBuf>,
+    pub scripts: Vec<ScriptConfig>,
+}
+
+/// A program that prints metrics to its standard output
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptConfig {
+    /// The name used in the logs
+    pub name: String,
+    /// The program followed by its arguments, it is run without a shell
+    pub command: Vec<String>,
+    /// Seconds between two runs
+    #[serde(default = "default_script_interval")]
+    pub interval: u64,
+    /// Seconds after which the script is killed
+    #[serde(default = "default_script_timeout")]
+    pub timeout: u64,
+}
+
+fn default_script_interval() -> u64 {
+    60
+}
+
+fn default_script_timeout() -> u64 {
+    10
+}
+
+/// Which sources the node answers and how often
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AccessConfig {
+    /// The networks of the managers, e.g. `10.0.0.0/8` or `192.168.1.10`.
+    /// Requests from every source are answered when the list is empty.
+    pub allow: Vec<Ipv4Cidr>,
+    pub rate_limit: RateLimitConfig,
+}
+
+/// A token bucket per source IP address
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RateLimitConfig {
+    /// The rate the bucket of a source refills at
+    pub requests_per_second: f64,
+    /// The size of the bucket, i.e. the number of requests a source can send at once
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 2.0,
+            burst: 10,
+        }
+    }
+}
+
+/// An IPv4 network such as `10.0.0.0/8`, a bare address is a network of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Ipv4Cidr {
+    network: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = u32::MAX
+            .checked_shl(32 - u32::from(self.prefix))
+            .unwrap_or(0);
+        u32::from(ip) & mask == u32::from(self.network) & mask
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv4CidrError(String);
+
+impl std::fmt::Display for Ipv4CidrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid IPv4 network: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for Ipv4CidrError {}
+
+impl std::str::FromStr for Ipv4Cidr {
+    type Err = Ipv4CidrError;
+
+    fn from_str(cidr: &str) -> Result<Self, Self::Err> {
+        let error = || Ipv4CidrError(cidr.to_string());
+        let (network, prefix) = match cidr.trim().split_once('/') {
+            Some((network, prefix)) => (network, prefix.parse::<u8>().map_err(|_| error())?),
+            None => (cidr.trim(), 32),
+        };
+        if prefix > 32 {
+            return Err(error());
+        }
+        Ok(Self {
+            network: network.parse::<Ipv4Addr>().map_err(|_| error())?,
+            prefix,
+        })
+    }
+}
+
+impl TryFrom<String> for Ipv4Cidr {
+    type Error = Ipv4CidrError;
+
+    fn try_from(cidr: String) -> Result<Self, Self::Error> {
+        cidr.parse()
+    }
+}
+
+impl NodeConfig {
+    /// Load the configuration from `$NODE_CONFIG` or `node_config.json`
+    pub fn load() -> Self {
+        crate::config::load(CONFIG_PATH_ENV, DEFAULT_CONFIG_PATH)
+    }
+}
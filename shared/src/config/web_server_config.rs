@@ -0,0 +1,150 @@
+//! The configuration of the web server, read on top of the `ManagerConfig`.
+
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+const CONFIG_PATH_ENV: &str = "WEB_SERVER_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "web_server_config.json";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WebServerConfig {
+    /// The address the HTTP API listens on
+    pub listen: SocketAddr,
+    pub auth: AuthConfig,
+    /// Serve HTTPS instead of plain HTTP
+    pub tls: Option<TlsConfig>,
+}
+
+impl WebServerConfig {
+    /// Load the configuration from `$WEB_SERVER_CONFIG` or `web_server_config.json`
+    pub fn load() -> Self {
+        crate::config::load(CONFIG_PATH_ENV, DEFAULT_CONFIG_PATH)
+    }
+}
+
+impl Default for WebServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            auth: AuthConfig::default(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsConfig {
+    /// The PEM file with the certificate chain, the server certificate first
+    pub certificate: PathBuf,
+    /// The PEM file with the private key
+    pub key: PathBuf,
+    /// Seconds between two checks whether the certificate, the key or the CA file changed.
+    /// A changed file is loaded without a restart, a broken one is logged and ignored.
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval: u64,
+    /// Redirect plain HTTP on this address to HTTPS, e.g. `0.0.0.0:80`
+    #[serde(default)]
+    pub http_redirect: Option<SocketAddr>,
+    #[serde(default)]
+    pub client_auth: Option<ClientAuthConfig>,
+}
+
+fn default_reload_interval() -> u64 {
+    10
+}
+
+/// Mutual TLS, clients authenticate with a certificate issued by one of the CAs
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientAuthConfig {
+    /// The PEM file with the CA certificates the client certificates are verified against
+    pub ca_certificates: PathBuf,
+    /// Reject clients without a certificate. Otherwise they may use the other ways to
+    /// authenticate.
+    #[serde(default)]
+    pub required: bool,
+    /// The role of a client that authenticated with a certificate
+    #[serde(default = "default_client_role")]
+    pub role: Role,
+}
+
+fn default_client_role() -> Role {
+    Role::Viewer
+}
+
+/// What a client may do, each role includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    /// Read the nodes, the summary, the events and the metrics
+    Viewer,
+    /// Change labels and groups, and send requests to the nodes
+    Operator,
+    /// Everything, including the removal of nodes
+    Admin,
+}
+
+/// The ways a client can authenticate. Authentication is disabled when none of them nor mutual
+/// TLS is configured, then every client gets the `anonymous_role`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AuthConfig {
+    /// Bearer tokens, e.g. for scripts and Prometheus
+    pub tokens: Vec<TokenConfig>,
+    /// HTTP basic authentication, e.g. for the dashboard
+    pub users: Vec<UserConfig>,
+    pub jwt: Option<JwtConfig>,
+    /// The role of a client without credentials. Without it such a client is rejected, or is a
+    /// viewer when authentication is disabled. An admin role without authentication has to be
+    /// set here explicitly.
+    pub anonymous_role: Option<Role>,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || !self.users.is_empty() || self.jwt.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenConfig {
+    /// Shown in the logs instead of the token
+    pub name: String,
+    /// The hex encoded SHA-256 hash of the token, e.g. from `printf %s "$TOKEN" | sha256sum`
+    pub sha256: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserConfig {
+    pub username: String,
+    /// An Argon2 hash in the PHC string format, e.g. from `argon2` of the reference
+    /// implementation with `-e`
+    pub password_hash: String,
+    pub role: Role,
+}
+
+/// Validation of JWTs issued by an OpenID Connect provider
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JwtConfig {
+    /// The `iss` claim the tokens must have
+    pub issuer: String,
+    /// The `aud` claim the tokens must have, usually the client ID
+    pub audience: String,
+    /// A JSON Web Key Set with the public keys of the provider, as served at its `jwks_uri`
+    pub jwks_file: PathBuf,
+    /// The claim holding the role names, either a string or an array of strings.
+    /// A token with several roles gets the highest one.
+    #[serde(default = "default_role_claim")]
+    pub role_claim: String,
+}
+
+fn default_role_claim() -> String {
+    String::from("roles")
+}
@@ -1,6 +1,9 @@
 pub(crate) mod commands;
-mod scan;
+pub mod config;
+pub mod scan;
 pub mod schemas;
 pub mod server;
 pub mod store;
+#[cfg(test)]
+pub(crate) mod test_support;
 pub(crate) mod utils;
@@ -1 +1,9 @@
+pub mod cgroup;
+pub mod collector;
+pub mod custom_metrics;
+pub mod disk;
+pub mod hardware;
+pub mod network;
+pub mod prometheus;
+pub mod sensors;
 pub mod usage;
@@ -0,0 +1,401 @@
+//! Container usage from the cgroup v2 hierarchy on Linux.
+//!
+//! Containers are found by the cgroup names their runtimes create, e.g.
+//! `system.slice/docker-<id>.scope` with the systemd driver or `docker/<id>` with the cgroupfs
+//! driver. Nodes without a unified cgroup v2 hierarchy report no containers.
+
+use crate::scan::hardware::read_trimmed;
+use crate::schemas::device_info::{ContainerUsage, ResourceLimits, Virtualization};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// Containers are never nested deeper than this, e.g. `kubepods.slice/<qos>/<pod>/<container>`
+const MAX_DEPTH: usize = 5;
+
+/// Whether the node has a unified cgroup v2 hierarchy
+pub(crate) fn is_cgroup_v2() -> bool {
+    Path::new(CGROUP_ROOT).join("cgroup.controllers").exists()
+}
+
+/// The cgroup of this process, which is the root when the node runs in its own cgroup namespace
+fn own_cgroup() -> Option<PathBuf> {
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))?
+        .trim_start_matches('/');
+    Some(Path::new(CGROUP_ROOT).join(path))
+}
+
+/// The limits of the cgroup of this process, only available on cgroup v2
+pub(crate) fn own_limits() -> Option<ResourceLimits> {
+    if !is_cgroup_v2() {
+        return None;
+    }
+    Some(limits(&own_cgroup()?))
+}
+
+fn limits(path: &Path) -> ResourceLimits {
+    // `cpu.max` holds the quota and the period in microseconds, e.g. `150000 100000`
+    let cpu_limit = read_trimmed(path.join("cpu.max")).and_then(|cpu_max| {
+        let mut fields = cpu_max.split_whitespace();
+        let quota = fields.next()?.parse::<u64>().ok()?;
+        let period = fields.next()?.parse::<u64>().ok()?;
+        (period > 0).then(|| quota * 1000 / period)
+    });
+    ResourceLimits {
+        cpu_limit,
+        memory_limit: read_counter(path.join("memory.max")),
+    }
+}
+
+/// A single number, `max` and missing files are `None`
+fn read_counter(path: impl AsRef<Path>) -> Option<u64> {
+    read_trimmed(path).and_then(|value| value.parse::<u64>().ok())
+}
+
+/// The runtime and the ID of a container cgroup
+fn container_of(parent: &str, name: &str) -> Option<(&'static str, String)> {
+    let scopes = [
+        ("docker-", "docker"),
+        ("cri-containerd-", "containerd"),
+        ("crio-", "cri-o"),
+        ("libpod-", "podman"),
+    ];
+    for (prefix, runtime) in scopes {
+        if let Some(id) = name
+            .strip_prefix(prefix)
+            .and_then(|id| id.strip_suffix(".scope"))
+            .filter(|id| is_container_id(id))
+        {
+            return Some((runtime, id.to_string()));
+        }
+    }
+    // the cgroupfs driver names the cgroup after the bare ID
+    if is_container_id(name) {
+        let runtime = if parent == "docker" {
+            "docker"
+        } else if parent.starts_with("pod") {
+            "containerd"
+        } else {
+            return None;
+        };
+        return Some((runtime, name.to_string()));
+    }
+    None
+}
+
+fn is_container_id(id: &str) -> bool {
+    id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The names of the child cgroups, the entry types spare a `stat` for each of the control files
+fn child_cgroups(directory: &Path) -> Vec<String> {
+    std::fs::read_dir(directory)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The cumulative counters of a cgroup
+#[derive(Debug, Clone, Copy)]
+struct CgroupCounters {
+    cpu_usage_usec: u64,
+    read_bytes: u64,
+    write_bytes: u64,
+}
+
+fn read_counters(path: &Path) -> CgroupCounters {
+    let cpu_usage_usec = std::fs::read_to_string(path.join("cpu.stat"))
+        .ok()
+        .and_then(|stat| {
+            stat.lines()
+                .find_map(|line| line.strip_prefix("usage_usec "))
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        })
+        .unwrap_or_default();
+
+    // one line per device, e.g. `8:0 rbytes=1024 wbytes=2048 rios=1 wios=2 dbytes=0 dios=0`
+    let (mut read_bytes, mut write_bytes) = (0, 0);
+    for field in std::fs::read_to_string(path.join("io.stat"))
+        .unwrap_or_default()
+        .split_whitespace()
+    {
+        if let Some(value) = field.strip_prefix("rbytes=") {
+            read_bytes += value.parse::<u64>().unwrap_or_default();
+        } else if let Some(value) = field.strip_prefix("wbytes=") {
+            write_bytes += value.parse::<u64>().unwrap_or_default();
+        }
+    }
+
+    CgroupCounters {
+        cpu_usage_usec,
+        read_bytes,
+        write_bytes,
+    }
+}
+
+/// The containers of the node and the cgroup of the node itself
+#[derive(Debug, Default)]
+pub(crate) struct CgroupUsage {
+    pub(crate) containers: Vec<ContainerUsage>,
+    pub(crate) own_container: Option<ContainerUsage>,
+}
+
+/// Turns the cumulative counters of the cgroups into rates per second.
+///
+/// The rates are computed against the previous sample, so the first sample reports them as zero.
+#[derive(Debug)]
+pub(crate) struct CgroupSampler {
+    enabled: bool,
+    root: PathBuf,
+    /// The cgroup and the runtime of the node, only set when it runs inside a container
+    own_cgroup: Option<(PathBuf, String)>,
+    previous: HashMap<PathBuf, CgroupCounters>,
+    previous_at: Option<Instant>,
+}
+
+impl CgroupSampler {
+    pub(crate) fn new(virtualization: &Virtualization) -> Self {
+        let enabled = is_cgroup_v2();
+        let own_cgroup = match virtualization {
+            Virtualization::Container { runtime } if enabled => {
+                own_cgroup().map(|path| (path, runtime.clone().unwrap_or_default()))
+            }
+            _ => None,
+        };
+        Self {
+            enabled,
+            root: PathBuf::from(CGROUP_ROOT),
+            own_cgroup,
+            previous: HashMap::new(),
+            previous_at: None,
+        }
+    }
+
+    pub(crate) fn sample(&mut self) -> CgroupUsage {
+        if !self.enabled {
+            return CgroupUsage::default();
+        }
+        let now = Instant::now();
+        let elapsed = self
+            .previous_at
+            .map(|previous_at| now.duration_since(previous_at).as_secs_f64())
+            .unwrap_or_default();
+
+        let mut current = HashMap::new();
+        let mut usage = |path: &Path, runtime: &str, id: String| {
+            let counters = read_counters(path);
+            let rate = |current: u64, previous: Option<u64>| match previous {
+                // the counters restart when a container with the same cgroup is recreated
+                Some(previous) if elapsed > 0.0 => {
+                    current.saturating_sub(previous) as f64 / elapsed
+                }
+                _ => 0.0,
+            };
+            let previous = self.previous.get(path);
+            let limits = limits(path);
+            // microseconds of CPU time per second, as percent of a single CPU
+            let cpu_usage = (rate(
+                counters.cpu_usage_usec,
+                previous.map(|previous| previous.cpu_usage_usec),
+            ) / 10_000.0) as f32;
+            let container = ContainerUsage {
+                id,
+                runtime: runtime.to_string(),
+                kubernetes: path.to_string_lossy().contains("kubepods"),
+                cgroup: path
+                    .strip_prefix(&self.root)
+                    .map(|relative| format!("/{}", relative.to_string_lossy()))
+                    .unwrap_or_default(),
+                cpu_usage,
+                // the limit is in thousandths of a CPU, the usage in percent of a CPU
+                cpu_limit_usage: limits
+                    .cpu_limit
+                    .filter(|limit| *limit > 0)
+                    .map(|limit| cpu_usage * 1000.0 / limit as f32),
+                memory_usage: read_counter(path.join("memory.current")).unwrap_or_default(),
+                limits,
+                read_bytes_per_second: rate(
+                    counters.read_bytes,
+                    previous.map(|previous| previous.read_bytes),
+                ),
+                write_bytes_per_second: rate(
+                    counters.write_bytes,
+                    previous.map(|previous| previous.write_bytes),
+                ),
+            };
+            current.insert(path.to_path_buf(), counters);
+            container
+        };
+
+        let mut containers = Vec::new();
+        let mut pending = vec![(self.root.clone(), 0)];
+        while let Some((directory, depth)) = pending.pop() {
+            let parent = directory
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            for name in child_cgroups(&directory) {
+                let path = directory.join(&name);
+                match container_of(&parent, &name) {
+                    Some((runtime, id)) => containers.push(usage(&path, runtime, id)),
+                    None if depth < MAX_DEPTH => pending.push((path, depth + 1)),
+                    None => {}
+                }
+            }
+        }
+        containers.sort_by(|a, b| a.cgroup.cmp(&b.cgroup));
+
+        // the ID is only known when the node sees the cgroup of its container by name
+        let own_container = self.own_cgroup.as_ref().map(|(path, runtime)| {
+            let id = path
+                .file_name()
+                .and_then(|name| container_of("", &name.to_string_lossy()))
+                .map(|(_, id)| id)
+                .unwrap_or_default();
+            usage(path, runtime, id)
+        });
+
+        self.previous = current;
+        self.previous_at = Some(now);
+        CgroupUsage {
+            containers,
+            own_container,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::time::Duration;
+
+    const LIMITED: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    const UNLIMITED: &str = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+
+    /// A cgroup v2 tree with a container of the systemd driver with a CPU limit and one of the
+    /// cgroupfs driver with a memory limit
+    fn cgroupfs(root: &TempDir) -> (String, String) {
+        let limited = format!("system.slice/docker-{}.scope", LIMITED);
+        root.write(&format!("{}/cpu.max", limited), "150000 100000\n");
+        root.write(&format!("{}/memory.max", limited), "max\n");
+        root.write(&format!("{}/memory.current", limited), "1048576\n");
+        root.write(
+            &format!("{}/cpu.stat", limited),
+            "usage_usec 1000000\nuser_usec 1\n",
+        );
+        root.write(
+            &format!("{}/io.stat", limited),
+            "8:0 rbytes=1000 wbytes=2000 rios=1 wios=2\n",
+        );
+
+        let unlimited = format!("docker/{}", UNLIMITED);
+        root.write(&format!("{}/cpu.max", unlimited), "max 100000\n");
+        root.write(&format!("{}/memory.max", unlimited), "536870912\n");
+        root.write(&format!("{}/memory.current", unlimited), "2048\n");
+        // not a container
+        root.write("user.slice/memory.current", "4096\n");
+        (limited, unlimited)
+    }
+
+    fn sampler(root: &TempDir, own_cgroup: Option<&str>) -> CgroupSampler {
+        CgroupSampler {
+            enabled: true,
+            root: root.path().to_path_buf(),
+            own_cgroup: own_cgroup.map(|cgroup| (root.path().join(cgroup), String::from("docker"))),
+            previous: HashMap::new(),
+            previous_at: None,
+        }
+    }
+
+    #[test]
+    fn limits_and_memory_are_read() {
+        let root = TempDir::new("cgroup-limits");
+        let (limited, unlimited) = cgroupfs(&root);
+        let usage = sampler(&root, None).sample();
+
+        let containers = usage
+            .containers
+            .iter()
+            .map(|container| (container.cgroup.as_str(), container))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(containers.len(), 2);
+
+        let container = containers[format!("/{}", limited).as_str()];
+        assert_eq!(
+            (container.id.as_str(), container.runtime.as_str()),
+            (LIMITED, "docker")
+        );
+        assert_eq!(
+            container.limits,
+            ResourceLimits {
+                cpu_limit: Some(1500),
+                memory_limit: None,
+            }
+        );
+        assert_eq!(container.memory_usage, 1048576);
+        // the rates need a previous sample
+        assert_eq!(container.cpu_usage, 0.0);
+
+        let container = containers[format!("/{}", unlimited).as_str()];
+        assert_eq!(
+            (container.id.as_str(), container.runtime.as_str()),
+            (UNLIMITED, "docker")
+        );
+        assert_eq!(
+            container.limits,
+            ResourceLimits {
+                cpu_limit: None,
+                memory_limit: Some(536870912),
+            }
+        );
+        assert_eq!(container.memory_usage, 2048);
+        assert_eq!(container.cpu_limit_usage, None);
+    }
+
+    #[test]
+    fn usage_is_the_rate_since_the_previous_sample() {
+        let root = TempDir::new("cgroup-rates");
+        let (limited, _) = cgroupfs(&root);
+        let mut sampler = sampler(&root, Some(&limited));
+        sampler.sample();
+
+        // 0.75 s of CPU time and 1000 bytes read in about a second
+        root.write(&format!("{}/cpu.stat", limited), "usage_usec 1750000\n");
+        root.write(
+            &format!("{}/io.stat", limited),
+            "8:0 rbytes=2000 wbytes=2000 rios=2 wios=2\n",
+        );
+        sampler.previous_at = Some(Instant::now() - Duration::from_secs(1));
+        let own_container = sampler.sample().own_container.unwrap();
+
+        assert_eq!(own_container.id, LIMITED);
+        assert!(
+            (70.0..=75.0).contains(&own_container.cpu_usage),
+            "{}",
+            own_container.cpu_usage
+        );
+        let cpu_limit_usage = own_container.cpu_limit_usage.unwrap();
+        assert!((own_container.cpu_usage / 1.5 - cpu_limit_usage).abs() < 0.01);
+        assert!((950.0..=1000.0).contains(&own_container.read_bytes_per_second));
+        assert_eq!(own_container.write_bytes_per_second, 0.0);
+    }
+
+    #[test]
+    fn nodes_without_cgroup_v2_report_no_containers() {
+        let root = TempDir::new("cgroup-disabled");
+        cgroupfs(&root);
+        let mut sampler = sampler(&root, None);
+        sampler.enabled = false;
+        assert!(sampler.sample().containers.is_empty());
+    }
+}
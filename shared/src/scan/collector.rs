@@ -0,0 +1,445 @@
+//! Collectors fill in the usage a node reports to the manager.
+//!
+//! [`SystemInfo`](crate::scan::usage::SystemInfo) runs the built-in collectors below in order,
+//! followed by the collectors registered at startup. Custom collectors publish their values as
+//! named [`Metric`]s, so they reach the data store without any change to the schemas.
+
+use crate::config::node_config::NetworkConfig;
+use crate::scan::cgroup::CgroupSampler;
+use crate::scan::disk::DiskIoSampler;
+use crate::scan::network::NetworkSampler;
+use crate::scan::{disk, sensors};
+use crate::schemas::device_info::{ContainerUsage, LoadAverage, MachineUsage, Virtualization};
+use std::path::Path;
+use std::sync::Mutex;
+use sysinfo::System;
+use tracing::warn;
+
+pub type CollectorError = Box<dyn std::error::Error + Send + Sync>;
+
+pub trait Collector: Send {
+    /// The name used in the logs when the collector fails
+    fn name(&self) -> &str;
+
+    /// Add the values of this collector to the usage.
+    /// The usage already holds the values of the collectors that ran before.
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError>;
+}
+
+/// The upper bounds of the lists in the usage, so the usage fits into a single datagram
+pub const MAX_FILESYSTEMS: usize = 64;
+pub const MAX_DISKS: usize = 32;
+pub const MAX_INTERFACES: usize = 32;
+pub const MAX_TEMPERATURES: usize = 64;
+pub const MAX_CONTAINERS: usize = 64;
+
+/// Keep the `limit` items ranking highest by `rank` in their order, the rest is dropped with a
+/// warning
+pub(crate) fn keep_top<T>(items: &mut Vec<T>, limit: usize, what: &str, rank: impl Fn(&T) -> f64) {
+    if items.len() <= limit {
+        return;
+    }
+    warn!(
+        "Dropping {} {} above the limit of {}",
+        items.len() - limit,
+        what,
+        limit
+    );
+    let mut ranked = (0..items.len()).collect::<Vec<usize>>();
+    ranked.sort_by(|a, b| rank(&items[*b]).total_cmp(&rank(&items[*a])));
+    let mut keep = vec![false; items.len()];
+    for index in ranked.into_iter().take(limit) {
+        keep[index] = true;
+    }
+    let mut keep = keep.into_iter();
+    items.retain(|_| keep.next().unwrap_or(false));
+}
+
+/// The usage and frequency of every logical CPU
+pub(crate) struct CpuCollector {
+    system: &'static Mutex<System>,
+}
+
+impl CpuCollector {
+    pub(crate) fn new(system: &'static Mutex<System>) -> Self {
+        Self { system }
+    }
+}
+
+impl Collector for CpuCollector {
+    fn name(&self) -> &str {
+        "cpu"
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        let mut system = self.system.lock().unwrap();
+        system.refresh_cpu_all();
+        for cpu in system.cpus().iter() {
+            usage.cpu_usage.push(cpu.cpu_usage());
+            usage.cpu_frequency.push(cpu.frequency());
+        }
+        Ok(())
+    }
+}
+
+/// The RAM and swap of the host
+pub(crate) struct MemoryCollector {
+    system: &'static Mutex<System>,
+}
+
+impl MemoryCollector {
+    pub(crate) fn new(system: &'static Mutex<System>) -> Self {
+        Self { system }
+    }
+}
+
+impl Collector for MemoryCollector {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        let mut system = self.system.lock().unwrap();
+        system.refresh_memory();
+        usage.total_memory = system.total_memory();
+        usage.used_memory = system.used_memory();
+        usage.total_swap = system.total_swap();
+        usage.used_swap = system.used_swap();
+        Ok(())
+    }
+}
+
+/// The throughput of the network interfaces that are not excluded by the configuration
+pub(crate) struct NetworkCollector {
+    sampler: NetworkSampler,
+    config: NetworkConfig,
+}
+
+impl NetworkCollector {
+    pub(crate) fn new(config: NetworkConfig) -> Self {
+        Self {
+            sampler: NetworkSampler::new(),
+            config,
+        }
+    }
+}
+
+impl Collector for NetworkCollector {
+    fn name(&self) -> &str {
+        "network"
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        let interfaces = self.sampler.sample(&self.config);
+        usage.network_down = interfaces
+            .iter()
+            .map(|interface| interface.received_bytes_per_second)
+            .sum::<f64>() as u64;
+        usage.network_up = interfaces
+            .iter()
+            .map(|interface| interface.transmitted_bytes_per_second)
+            .sum::<f64>() as u64;
+        usage.interfaces = interfaces;
+        // the totals above include every interface, only the busiest ones are listed
+        keep_top(
+            &mut usage.interfaces,
+            MAX_INTERFACES,
+            "network interfaces",
+            |interface| {
+                interface.received_bytes_per_second + interface.transmitted_bytes_per_second
+            },
+        );
+        Ok(())
+    }
+}
+
+/// The load average, the uptime and the number of processes and threads
+pub(crate) struct LoadCollector {
+    system: &'static Mutex<System>,
+}
+
+impl LoadCollector {
+    pub(crate) fn new(system: &'static Mutex<System>) -> Self {
+        Self { system }
+    }
+}
+
+impl Collector for LoadCollector {
+    fn name(&self) -> &str {
+        "load"
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        let load_average = System::load_average();
+        usage.load_average = LoadAverage {
+            one: load_average.one,
+            five: load_average.five,
+            fifteen: load_average.fifteen,
+        };
+        usage.uptime = System::uptime();
+
+        // on Linux both counts are read from /proc, without refreshing every process under the
+        // lock shared with the other collectors
+        let process_count = count_processes(Path::new("/proc"));
+        let thread_count = crate::scan::hardware::read_trimmed("/proc/loadavg")
+            .and_then(|loadavg| scheduling_entities(&loadavg));
+        if let (Some(process_count), Some(thread_count)) = (process_count, thread_count) {
+            usage.process_count = process_count;
+            usage.thread_count = thread_count;
+            return Ok(());
+        }
+
+        // elsewhere the tasks known to `sysinfo` are counted
+        let mut system = self.system.lock().unwrap();
+        system.refresh_processes_specifics(
+            sysinfo::ProcessesToUpdate::All,
+            true,
+            sysinfo::ProcessRefreshKind::nothing(),
+        );
+        let processes = || {
+            system
+                .processes()
+                .values()
+                .filter(|process| process.thread_kind().is_none())
+        };
+        usage.process_count = process_count.unwrap_or_else(|| processes().count());
+        usage.thread_count = thread_count.unwrap_or_else(|| {
+            processes()
+                .map(|process| process.tasks().map_or(1, |tasks| tasks.len().max(1)))
+                .sum()
+        });
+        Ok(())
+    }
+}
+
+/// The number of processes, i.e. the numeric directories of a procfs. The threads are not listed
+/// there. `None` if there is no procfs.
+fn count_processes(proc: &Path) -> Option<usize> {
+    let entries = std::fs::read_dir(proc).ok()?;
+    let count = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.bytes().all(|byte| byte.is_ascii_digit()))
+        })
+        .count();
+    // a procfs always lists at least the process reading it
+    (count > 0).then_some(count)
+}
+
+/// The number of scheduling entities in `/proc/loadavg`, e.g. `1024` in
+/// `0.20 0.18 0.12 1/1024 12345`. It counts every thread, including the kernel threads.
+fn scheduling_entities(loadavg: &str) -> Option<usize> {
+    let entities = loadavg.split_whitespace().nth(3)?;
+    entities.split_once('/')?.1.parse::<usize>().ok()
+}
+
+/// The capacity of the filesystems and the throughput of the block devices
+pub(crate) struct DiskCollector {
+    disks: sysinfo::Disks,
+    disk_io: DiskIoSampler,
+}
+
+impl DiskCollector {
+    pub(crate) fn new() -> Self {
+        Self {
+            disks: sysinfo::Disks::new_with_refreshed_list(),
+            disk_io: DiskIoSampler::default(),
+        }
+    }
+}
+
+impl Collector for DiskCollector {
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        self.disks.refresh(true);
+        usage.filesystems = disk::filesystems(&self.disks);
+        // the largest filesystems and the busiest devices are kept
+        keep_top(
+            &mut usage.filesystems,
+            MAX_FILESYSTEMS,
+            "filesystems",
+            |filesystem| filesystem.total_space as f64,
+        );
+        usage.disk_io = self.disk_io.sample();
+        keep_top(&mut usage.disk_io, MAX_DISKS, "block devices", |disk| {
+            disk.read_bytes_per_second + disk.write_bytes_per_second
+        });
+        Ok(())
+    }
+}
+
+/// The temperature sensors
+pub(crate) struct SensorCollector {
+    components: sysinfo::Components,
+}
+
+impl SensorCollector {
+    pub(crate) fn new() -> Self {
+        Self {
+            components: sysinfo::Components::new_with_refreshed_list(),
+        }
+    }
+}
+
+impl Collector for SensorCollector {
+    fn name(&self) -> &str {
+        "sensors"
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        self.components.refresh(true);
+        usage.temperatures = sensors::temperatures(&self.components);
+        // the hottest sensors are kept
+        keep_top(
+            &mut usage.temperatures,
+            MAX_TEMPERATURES,
+            "temperature sensors",
+            |reading| f64::from(reading.temperature),
+        );
+        Ok(())
+    }
+}
+
+/// The containers of the node.
+/// When the node itself runs inside a container, the memory of the host is replaced by the memory
+/// of its cgroup, so it has to run after the [`MemoryCollector`] and the [`CpuCollector`]. The CPU
+/// usage per CPU stays the one of the host, the CPUs the container can use and its usage of them
+/// are reported as the effective CPUs.
+pub(crate) struct CgroupCollector {
+    sampler: CgroupSampler,
+}
+
+impl CgroupCollector {
+    pub(crate) fn new(virtualization: &Virtualization) -> Self {
+        Self {
+            sampler: CgroupSampler::new(virtualization),
+        }
+    }
+}
+
+/// The CPUs a container can use and its usage in percent of them.
+/// The quota is capped at the logical CPUs of the host, a container without a quota can use all
+/// of them.
+fn effective_cpu(container: &ContainerUsage, logical_cpus: usize) -> Option<(f32, f32)> {
+    let quota = container
+        .limits
+        .cpu_limit
+        .map(|limit| limit as f32 / 1000.0);
+    let cpus = match (quota, logical_cpus) {
+        (Some(quota), 0) => quota,
+        (Some(quota), logical_cpus) => quota.min(logical_cpus as f32),
+        (None, logical_cpus) => logical_cpus as f32,
+    };
+    (cpus > 0.0).then(|| (cpus, container.cpu_usage / cpus))
+}
+
+impl Collector for CgroupCollector {
+    fn name(&self) -> &str {
+        "cgroup"
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        let cgroups = self.sampler.sample();
+        if let Some(own_container) = &cgroups.own_container {
+            if let Some(limit) = own_container.limits.memory_limit {
+                usage.total_memory = limit.min(usage.total_memory);
+            }
+            usage.used_memory = own_container.memory_usage;
+            if let Some((cpus, cpu_usage)) = effective_cpu(own_container, usage.cpu_usage.len()) {
+                usage.effective_cpus = Some(cpus);
+                usage.effective_cpu_usage = Some(cpu_usage);
+            }
+        }
+        usage.containers = cgroups.containers;
+        // the CPU usage is zero on the first sample, the memory usage keeps the list stable
+        keep_top(
+            &mut usage.containers,
+            MAX_CONTAINERS,
+            "containers",
+            |container| container.memory_usage as f64,
+        );
+        usage.own_container = cgroups.own_container;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::device_info::ResourceLimits;
+    use crate::test_support::TempDir;
+
+    fn container(cpu_limit: Option<u64>, cpu_usage: f32) -> ContainerUsage {
+        ContainerUsage {
+            id: String::new(),
+            runtime: String::from("docker"),
+            kubernetes: false,
+            cgroup: String::from("/"),
+            cpu_usage,
+            cpu_limit_usage: None,
+            memory_usage: 0,
+            limits: ResourceLimits {
+                cpu_limit,
+                memory_limit: None,
+            },
+            read_bytes_per_second: 0.0,
+            write_bytes_per_second: 0.0,
+        }
+    }
+
+    #[test]
+    fn effective_cpus_follow_the_quota() {
+        // 1.5 CPUs, 75 % of a single CPU used
+        assert_eq!(
+            effective_cpu(&container(Some(1500), 75.0), 8),
+            Some((1.5, 50.0))
+        );
+        // a quota above the CPUs of the host cannot be used
+        assert_eq!(
+            effective_cpu(&container(Some(16000), 200.0), 4),
+            Some((4.0, 50.0))
+        );
+        assert_eq!(effective_cpu(&container(None, 200.0), 8), Some((8.0, 25.0)));
+        assert_eq!(
+            effective_cpu(&container(Some(500), 25.0), 0),
+            Some((0.5, 50.0))
+        );
+        assert_eq!(effective_cpu(&container(None, 0.0), 0), None);
+    }
+
+    #[test]
+    fn processes_are_the_numeric_proc_directories() {
+        let proc = TempDir::new("collector-proc");
+        for entry in [
+            "1/stat",
+            "42/stat",
+            "4242/stat",
+            "self/stat",
+            "sys/kernel",
+            "net/dev",
+        ] {
+            proc.write(entry, "");
+        }
+        assert_eq!(count_processes(proc.path()), Some(3));
+        assert_eq!(count_processes(&proc.path().join("missing")), None);
+
+        let empty = TempDir::new("collector-proc-empty");
+        assert_eq!(count_processes(empty.path()), None);
+    }
+
+    #[test]
+    fn threads_are_the_scheduling_entities() {
+        assert_eq!(
+            scheduling_entities("0.20 0.18 0.12 1/1024 12345"),
+            Some(1024)
+        );
+        assert_eq!(scheduling_entities("0.20 0.18 0.12"), None);
+        assert_eq!(scheduling_entities("0.20 0.18 0.12 1024 12345"), None);
+    }
+}
@@ -0,0 +1,291 @@
+//! Custom metrics in the Prometheus text format, read from textfiles or the output of scripts.
+//!
+//! Teams can add metrics to a node without rebuilding it, either by dropping `*.prom` files into
+//! the configured directory, e.g. from a cron job, or by configuring a script the node runs itself.
+
+use crate::config::node_config::ScriptConfig;
+use crate::scan::collector::{Collector, CollectorError};
+use crate::scan::prometheus;
+use crate::schemas::device_info::{MachineUsage, Metric};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Reads every `*.prom` file of a directory on each collection.
+/// A file that cannot be read is skipped, so it does not hide the metrics of the other files.
+pub(crate) struct TextfileCollector {
+    directory: PathBuf,
+}
+
+impl TextfileCollector {
+    pub(crate) fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+}
+
+impl Collector for TextfileCollector {
+    fn name(&self) -> &str {
+        "textfile"
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        let mut paths = std::fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "prom")
+            })
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+
+        for path in paths {
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    warn!("Skipping the metrics file {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            let parsed = prometheus::parse(&text);
+            for e in parsed.errors {
+                warn!("Skipping a sample of the metrics file {:?}: {}", path, e);
+            }
+            usage.metrics.extend(parsed.metrics);
+        }
+        Ok(())
+    }
+}
+
+/// Reports the metrics of the latest run of a script.
+/// The script runs on its own thread at the configured interval, so a slow script never delays a
+/// usage response. The metrics are dropped when a run fails, rather than reporting stale values.
+pub(crate) struct ScriptCollector {
+    name: String,
+    metrics: Arc<Mutex<Vec<Metric>>>,
+}
+
+impl ScriptCollector {
+    /// Start running the script in the background
+    pub(crate) fn start(config: ScriptConfig) -> Self {
+        let metrics = Arc::new(Mutex::new(Vec::new()));
+        let name = config.name.clone();
+
+        let latest = Arc::clone(&metrics);
+        std::thread::Builder::new()
+            .name(format!("script-{}", config.name))
+            .spawn(move || {
+                loop {
+                    let result = run_script(&config);
+                    let mut latest = latest.lock().unwrap();
+                    match result {
+                        Ok(metrics) => *latest = metrics,
+                        Err(e) => {
+                            error!("Script {} failed: {}", config.name, e);
+                            latest.clear();
+                        }
+                    }
+                    drop(latest);
+                    std::thread::sleep(Duration::from_secs(config.interval.max(1)));
+                }
+            })
+            .expect("failed to spawn a script thread");
+
+        Self { name, metrics }
+    }
+}
+
+impl Collector for ScriptCollector {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn collect(&mut self, usage: &mut MachineUsage) -> Result<(), CollectorError> {
+        usage
+            .metrics
+            .extend(self.metrics.lock().unwrap().iter().cloned());
+        Ok(())
+    }
+}
+
+/// The upper bound of the output of a script, a longer output fails the run
+const MAX_SCRIPT_OUTPUT: u64 = 1024 * 1024;
+
+/// Run the script and parse its standard output, killing it when it exceeds the timeout
+fn run_script(config: &ScriptConfig) -> Result<Vec<Metric>, CollectorError> {
+    let (program, arguments) = config.command.split_first().ok_or("the command is empty")?;
+    let mut command = Command::new(program);
+    command
+        .args(arguments)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    // the script and its children get a process group of their own, so they are killed together
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.spawn()?;
+
+    // the output is read on another thread, so the timeout also covers a script that never exits
+    // or leaves a child behind that holds the standard output open
+    let stdout = child.stdout.take().ok_or("no standard output")?;
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        let result = stdout
+            .take(MAX_SCRIPT_OUTPUT + 1)
+            .read_to_string(&mut output)
+            .map(|_| output);
+        let _ = sender.send(result);
+    });
+
+    let output = match receiver.recv_timeout(Duration::from_secs(config.timeout)) {
+        Ok(Ok(output)) if output.len() as u64 > MAX_SCRIPT_OUTPUT => {
+            kill(&mut child);
+            return Err(format!("the output exceeds {} bytes", MAX_SCRIPT_OUTPUT).into());
+        }
+        Ok(output) => output,
+        Err(_) => {
+            kill(&mut child);
+            return Err(format!("timed out after {} seconds", config.timeout).into());
+        }
+    };
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("exited with {}", status).into());
+    }
+    let parsed = prometheus::parse(&output?);
+    for e in parsed.errors {
+        warn!("Skipping a sample of the script {}: {}", config.name, e);
+    }
+    Ok(parsed.metrics)
+}
+
+/// Kill the script together with the processes it started, which closes the standard output
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: signalling a process group has no memory safety requirements
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::time::Instant;
+
+    fn collect(collector: &mut dyn Collector) -> Result<Vec<Metric>, CollectorError> {
+        let mut usage = MachineUsage::default();
+        collector.collect(&mut usage)?;
+        Ok(usage.metrics)
+    }
+
+    fn script(command: &str, timeout: u64) -> ScriptConfig {
+        ScriptConfig {
+            name: String::from("test"),
+            command: vec![String::from("sh"), String::from("-c"), command.to_string()],
+            interval: 60,
+            timeout,
+        }
+    }
+
+    #[test]
+    fn textfiles_are_read_in_order() {
+        let directory = TempDir::new("textfiles");
+        directory.write("b.prom", "second 2\n");
+        directory.write(
+            "a.prom",
+            "# TYPE first gauge\nfirst{a=\"1\"} 1\nnot a sample\n",
+        );
+        directory.write("c.txt", "ignored 3\n");
+        // a directory with the extension cannot be read as a file
+        std::fs::create_dir(directory.path().join("d.prom")).unwrap();
+
+        let mut collector = TextfileCollector::new(directory.path().to_path_buf());
+        assert_eq!(
+            collect(&mut collector).unwrap(),
+            vec![
+                Metric::new("first", 1.0).with_label("a", "1"),
+                Metric::new("second", 2.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_textfile_directory_fails() {
+        let directory = TempDir::new("textfiles-missing");
+        let mut missing = TextfileCollector::new(directory.path().join("missing"));
+        assert!(collect(&mut missing).is_err());
+        let file = directory.write("file", "");
+        let mut not_a_directory = TextfileCollector::new(file);
+        assert!(collect(&mut not_a_directory).is_err());
+    }
+
+    #[test]
+    fn script_output_is_parsed() {
+        let metrics = run_script(&script("echo 'jobs{state=\"done\"} 3'", 5)).unwrap();
+        assert_eq!(
+            metrics,
+            vec![Metric::new("jobs", 3.0).with_label("state", "done")]
+        );
+
+        let error = run_script(&script("echo 'jobs 3'; exit 2", 5)).unwrap_err();
+        assert!(error.to_string().starts_with("exited with"));
+        assert!(run_script(&script("", 5)).unwrap().is_empty());
+
+        let empty = ScriptConfig {
+            command: Vec::new(),
+            ..script("", 5)
+        };
+        assert_eq!(
+            run_script(&empty).unwrap_err().to_string(),
+            "the command is empty"
+        );
+    }
+
+    #[test]
+    fn script_output_is_bounded() {
+        let error = run_script(&script("yes 'jobs 1' | head -c 2000000", 5)).unwrap_err();
+        assert_eq!(error.to_string(), "the output exceeds 1048576 bytes");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn script_is_killed_with_its_children_on_timeout() {
+        let directory = TempDir::new("script-timeout");
+        let pid_file = directory.path().join("pid");
+        // the background child keeps the standard output open after the script is killed
+        let command = format!("sleep 60 & echo $! > {}; wait", pid_file.display());
+
+        let started = Instant::now();
+        let error = run_script(&script(&command, 1)).unwrap_err();
+        assert_eq!(error.to_string(), "timed out after 1 seconds");
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        // the orphaned child is gone, or a zombie nobody reaps in a minimal container.
+        // SIGKILL is delivered asynchronously, so the child may take a moment to exit.
+        let state = || {
+            std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+                .ok()
+                .and_then(|stat| stat.rsplit_once(") ")?.1.chars().next())
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !matches!(state(), None | Some('Z') | Some('X')) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(
+            matches!(state(), None | Some('Z') | Some('X')),
+            "{:?}",
+            state()
+        );
+    }
+}
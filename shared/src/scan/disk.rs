@@ -0,0 +1,120 @@
+//! Filesystem capacity and block device throughput.
+
+use crate::schemas::device_info::{DiskIoUsage, FilesystemUsage};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// The capacity and usage of every mounted filesystem
+pub(crate) fn filesystems(disks: &sysinfo::Disks) -> Vec<FilesystemUsage> {
+    let mut filesystems = disks
+        .list()
+        .iter()
+        .map(|disk| FilesystemUsage {
+            mount_point: disk.mount_point().to_string_lossy().to_string(),
+            device: disk.name().to_string_lossy().to_string(),
+            file_system: disk.file_system().to_string_lossy().to_string(),
+            total_space: disk.total_space(),
+            available_space: disk.available_space(),
+            used_space: disk.total_space().saturating_sub(disk.available_space()),
+        })
+        .collect::<Vec<FilesystemUsage>>();
+    filesystems.sort_by(|a, b| a.mount_point.cmp(&b.mount_point));
+    filesystems
+}
+
+/// The cumulative counters of a block device as found in `/proc/diskstats`
+#[derive(Debug, Clone, Copy)]
+struct DiskCounters {
+    reads_completed: u64,
+    sectors_read: u64,
+    writes_completed: u64,
+    sectors_written: u64,
+}
+
+/// Turns the cumulative counters of `/proc/diskstats` into rates per second.
+///
+/// The rates are computed against the previous sample, so the first sample returns no devices.
+#[derive(Debug, Default)]
+pub(crate) struct DiskIoSampler {
+    previous: HashMap<String, DiskCounters>,
+    previous_at: Option<Instant>,
+}
+
+impl DiskIoSampler {
+    pub(crate) fn sample(&mut self) -> Vec<DiskIoUsage> {
+        let now = Instant::now();
+        let current = read_diskstats();
+
+        let mut usage = Vec::new();
+        if let Some(previous_at) = self.previous_at {
+            let elapsed = now.duration_since(previous_at).as_secs_f64();
+            if elapsed > 0.0 {
+                for (device, counters) in current.iter() {
+                    let Some(previous) = self.previous.get(device) else {
+                        continue;
+                    };
+                    // the counters are reset when a device is re-attached
+                    let delta =
+                        |current: u64, previous: u64| current.saturating_sub(previous) as f64;
+                    usage.push(DiskIoUsage {
+                        device: device.clone(),
+                        read_bytes_per_second: delta(counters.sectors_read, previous.sectors_read)
+                            * SECTOR_SIZE
+                            / elapsed,
+                        write_bytes_per_second: delta(
+                            counters.sectors_written,
+                            previous.sectors_written,
+                        ) * SECTOR_SIZE
+                            / elapsed,
+                        read_iops: delta(counters.reads_completed, previous.reads_completed)
+                            / elapsed,
+                        write_iops: delta(counters.writes_completed, previous.writes_completed)
+                            / elapsed,
+                    });
+                }
+            }
+        }
+        usage.sort_by(|a, b| a.device.cmp(&b.device));
+
+        self.previous = current;
+        self.previous_at = Some(now);
+        usage
+    }
+}
+
+/// `/proc/diskstats` always counts in 512 byte sectors, whatever the sector size of the device is
+const SECTOR_SIZE: f64 = 512.0;
+
+/// Read the counters of the physical block devices, partitions and virtual devices are skipped
+fn read_diskstats() -> HashMap<String, DiskCounters> {
+    let Ok(diskstats) = std::fs::read_to_string("/proc/diskstats") else {
+        return HashMap::new();
+    };
+    diskstats
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<&str>>();
+            if fields.len() < 10 {
+                return None;
+            }
+            let device = fields[2];
+            if !std::path::Path::new("/sys/block")
+                .join(device)
+                .join("device")
+                .exists()
+            {
+                return None;
+            }
+            let field = |index: usize| fields[index].parse::<u64>().unwrap_or_default();
+            Some((
+                device.to_string(),
+                DiskCounters {
+                    reads_completed: field(3),
+                    sectors_read: field(5),
+                    writes_completed: field(7),
+                    sectors_written: field(9),
+                },
+            ))
+        })
+        .collect()
+}
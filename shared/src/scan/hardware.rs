@@ -0,0 +1,252 @@
+//! Hardware inventory read from `/sys` and `/proc` on Linux.
+//!
+//! Every function returns an empty list or `None` when the files are not available, e.g. on other
+//! operating systems or inside restricted containers, so the callers do not need to care.
+
+use crate::schemas::device_info::{
+    DiskInfo, GpuInfo, MemoryModuleInfo, NetworkInterfaceInfo, Virtualization,
+};
+use std::path::Path;
+
+/// Read a file and trim it, empty files are treated as missing.
+pub(crate) fn read_trimmed(path: impl AsRef<Path>) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+        .filter(|content| !content.is_empty())
+}
+
+/// The sorted names of the entries in a directory
+pub(crate) fn read_dir_names(path: impl AsRef<Path>) -> Vec<String> {
+    let mut names = std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// A field of the DMI table, e.g. `sys_vendor`, `product_name` or `bios_version`
+pub(crate) fn dmi(field: &str) -> Option<String> {
+    read_trimmed(Path::new("/sys/class/dmi/id").join(field))
+}
+
+/// The ID the OS generated at installation, it survives changes of the IP address and the host
+/// name. Cloned images share it unless it was reset after cloning.
+pub(crate) fn machine_id() -> Option<String> {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .find_map(read_trimmed)
+}
+
+/// The physical block devices.
+/// Virtual devices such as loop, ram and device mapper devices have no `device` link and are skipped.
+pub(crate) fn disks() -> Vec<DiskInfo> {
+    let block = Path::new("/sys/block");
+    read_dir_names(block)
+        .into_iter()
+        .filter(|name| block.join(name).join("device").exists())
+        .map(|name| {
+            let base = block.join(&name);
+            DiskInfo {
+                model: read_trimmed(base.join("device/model")),
+                // the size is always given in 512 byte sectors
+                size: read_trimmed(base.join("size"))
+                    .and_then(|size| size.parse::<u64>().ok())
+                    .unwrap_or_default()
+                    * 512,
+                rotational: read_trimmed(base.join("queue/rotational")).map(|value| value == "1"),
+                name,
+            }
+        })
+        .collect()
+}
+
+/// The memory modules known to the EDAC driver.
+/// Without an EDAC driver, which is common on desktops and VMs, the list is empty.
+pub(crate) fn memory_modules() -> Vec<MemoryModuleInfo> {
+    let edac = Path::new("/sys/devices/system/edac/mc");
+    let mut modules = Vec::new();
+    for controller in read_dir_names(edac)
+        .into_iter()
+        .filter(|name| name.starts_with("mc"))
+    {
+        let controller = edac.join(controller);
+        for dimm in read_dir_names(&controller)
+            .into_iter()
+            .filter(|name| name.starts_with("dimm") || name.starts_with("rank"))
+        {
+            let base = controller.join(&dimm);
+            // the size is given in MiB
+            let Some(size) =
+                read_trimmed(base.join("size")).and_then(|size| size.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if size == 0 {
+                continue;
+            }
+            modules.push(MemoryModuleInfo {
+                label: read_trimmed(base.join("dimm_label")).unwrap_or(dimm),
+                size: size * 1024 * 1024,
+                memory_type: read_trimmed(base.join("dimm_mem_type")),
+            });
+        }
+    }
+    modules
+}
+
+/// The display controllers on the PCI bus
+pub(crate) fn gpus() -> Vec<GpuInfo> {
+    let pci = Path::new("/sys/bus/pci/devices");
+    read_dir_names(pci)
+        .into_iter()
+        .filter(|address| {
+            // PCI class 0x03 is the display controller class
+            read_trimmed(pci.join(address).join("class"))
+                .is_some_and(|class| class.starts_with("0x03"))
+        })
+        .map(|address| {
+            let base = pci.join(&address);
+            let vendor_id = read_trimmed(base.join("vendor")).unwrap_or_default();
+            GpuInfo {
+                vendor: pci_vendor_name(&vendor_id)
+                    .map(str::to_string)
+                    .unwrap_or(vendor_id),
+                device_id: read_trimmed(base.join("device")).unwrap_or_default(),
+                driver: std::fs::read_link(base.join("driver"))
+                    .ok()
+                    .and_then(|driver| {
+                        driver
+                            .file_name()
+                            .map(|name| name.to_string_lossy().to_string())
+                    }),
+                pci_address: address,
+            }
+        })
+        .collect()
+}
+
+fn pci_vendor_name(vendor_id: &str) -> Option<&'static str> {
+    match vendor_id {
+        "0x10de" => Some("NVIDIA"),
+        "0x1002" => Some("AMD"),
+        "0x8086" => Some("Intel"),
+        "0x1af4" => Some("Red Hat (virtio)"),
+        "0x15ad" => Some("VMware"),
+        "0x1234" => Some("QEMU"),
+        "0x1414" => Some("Microsoft"),
+        "0x102b" => Some("Matrox"),
+        "0x1a03" => Some("ASPEED"),
+        _ => None,
+    }
+}
+
+/// The network interfaces with their addresses, the link speed is read from `/sys/class/net`
+pub(crate) fn network_interfaces(networks: &sysinfo::Networks) -> Vec<NetworkInterfaceInfo> {
+    let mut interfaces = networks
+        .list()
+        .iter()
+        .map(|(name, data)| {
+            // sorted, so the order never shows up as a spec change
+            let mut ip_addresses = data
+                .ip_networks()
+                .iter()
+                .map(|network| format!("{}/{}", network.addr, network.prefix))
+                .collect::<Vec<String>>();
+            ip_addresses.sort();
+            NetworkInterfaceInfo {
+                name: name.clone(),
+                mac_address: data.mac_address().to_string(),
+                ip_addresses,
+                // a link that is down reports -1
+                speed: read_trimmed(Path::new("/sys/class/net").join(name).join("speed"))
+                    .and_then(|speed| speed.parse::<u64>().ok()),
+            }
+        })
+        .collect::<Vec<NetworkInterfaceInfo>>();
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    interfaces
+}
+
+/// Detect whether the node runs in a container or a virtual machine.
+/// Containers are checked first, since a container shows the DMI table of its host.
+pub(crate) fn virtualization() -> Virtualization {
+    if let Some(runtime) = container_runtime() {
+        return Virtualization::Container {
+            runtime: Some(runtime.to_string()),
+        };
+    }
+
+    let cpu_flags_hypervisor = std::fs::read_to_string("/proc/cpuinfo")
+        .map(|cpuinfo| {
+            cpuinfo
+                .lines()
+                .filter(|line| line.starts_with("flags"))
+                .any(|line| line.split_whitespace().any(|flag| flag == "hypervisor"))
+        })
+        .unwrap_or(false);
+    let hypervisor = hypervisor_name();
+    if cpu_flags_hypervisor || hypervisor.is_some() {
+        return Virtualization::VirtualMachine {
+            hypervisor: hypervisor.map(str::to_string),
+        };
+    }
+
+    Virtualization::BareMetal
+}
+
+fn container_runtime() -> Option<&'static str> {
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker");
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman");
+    }
+    if std::env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+        return Some("kubernetes");
+    }
+    let cgroup = std::fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+    if cgroup.contains("kubepods") {
+        Some("kubernetes")
+    } else if cgroup.contains("docker") {
+        Some("docker")
+    } else if cgroup.contains("libpod") {
+        Some("podman")
+    } else if cgroup.contains("lxc") {
+        Some("lxc")
+    } else {
+        None
+    }
+}
+
+/// The hypervisor as told by the DMI table or `/sys/hypervisor`
+fn hypervisor_name() -> Option<&'static str> {
+    let dmi_fields = [dmi("sys_vendor"), dmi("product_name"), dmi("bios_vendor")];
+    let known = [
+        ("QEMU", "KVM"),
+        ("KVM", "KVM"),
+        ("VMware", "VMware"),
+        ("VirtualBox", "VirtualBox"),
+        ("innotek", "VirtualBox"),
+        // the vendor alone would match Surface devices as well
+        ("Virtual Machine", "Hyper-V"),
+        ("Xen", "Xen"),
+        ("Amazon EC2", "AWS Nitro"),
+        ("Google Compute Engine", "Google Compute Engine"),
+        ("Parallels", "Parallels"),
+    ];
+    for field in dmi_fields.iter().flatten() {
+        if let Some((_, name)) = known.iter().find(|(needle, _)| field.contains(needle)) {
+            return Some(name);
+        }
+    }
+    match read_trimmed("/sys/hypervisor/type").as_deref() {
+        Some("xen") => Some("Xen"),
+        _ => None,
+    }
+}
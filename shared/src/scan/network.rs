@@ -0,0 +1,96 @@
+//! Per-interface network throughput.
+
+use crate::config::node_config::NetworkConfig;
+use crate::scan::hardware::read_trimmed;
+use crate::schemas::device_info::InterfaceUsage;
+use std::path::Path;
+use std::time::Instant;
+
+/// Turns the counters of `sysinfo::Networks` into rates per second.
+///
+/// `sysinfo` reports the bytes and packets since the previous refresh, so the rates are the
+/// deltas divided by the time elapsed since the previous sample.
+#[derive(Debug)]
+pub(crate) struct NetworkSampler {
+    networks: sysinfo::Networks,
+    previous_at: Instant,
+}
+
+impl NetworkSampler {
+    pub(crate) fn new() -> Self {
+        Self {
+            networks: sysinfo::Networks::new_with_refreshed_list(),
+            previous_at: Instant::now(),
+        }
+    }
+
+    /// The usage of every interface that is not excluded by the configuration
+    pub(crate) fn sample(&mut self, config: &NetworkConfig) -> Vec<InterfaceUsage> {
+        let now = Instant::now();
+        self.networks.refresh(true);
+        let elapsed = now.duration_since(self.previous_at).as_secs_f64();
+        self.previous_at = now;
+
+        let rate = |delta: u64| {
+            if elapsed > 0.0 {
+                delta as f64 / elapsed
+            } else {
+                0.0
+            }
+        };
+
+        let mut interfaces = self
+            .networks
+            .list()
+            .iter()
+            .filter(|(name, _)| !is_excluded(name, config))
+            .map(|(name, data)| InterfaceUsage {
+                name: name.clone(),
+                received_bytes_per_second: rate(data.received()),
+                transmitted_bytes_per_second: rate(data.transmitted()),
+                received_packets_per_second: rate(data.packets_received()),
+                transmitted_packets_per_second: rate(data.packets_transmitted()),
+                received_errors: data.total_errors_on_received(),
+                transmitted_errors: data.total_errors_on_transmitted(),
+                received_dropped: read_statistic(name, "rx_dropped"),
+                transmitted_dropped: read_statistic(name, "tx_dropped"),
+            })
+            .collect::<Vec<InterfaceUsage>>();
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+        interfaces
+    }
+}
+
+fn is_excluded(name: &str, config: &NetworkConfig) -> bool {
+    if config
+        .exclude
+        .iter()
+        .any(|prefix| name.starts_with(prefix.as_str()))
+    {
+        return true;
+    }
+    config.exclude_virtual && is_virtual(name)
+}
+
+/// Loopback, bridges, veth pairs, tunnels and the like.
+/// On Linux they live under `/sys/devices/virtual/net`, elsewhere only the loopback is detected.
+fn is_virtual(name: &str) -> bool {
+    if name == "lo" || name.starts_with("lo0") {
+        return true;
+    }
+    std::fs::canonicalize(Path::new("/sys/class/net").join(name))
+        .map(|path| path.starts_with("/sys/devices/virtual"))
+        .unwrap_or(false)
+}
+
+/// A cumulative counter from `/sys/class/net/<name>/statistics`, zero where it is not available
+fn read_statistic(name: &str, statistic: &str) -> u64 {
+    read_trimmed(
+        Path::new("/sys/class/net")
+            .join(name)
+            .join("statistics")
+            .join(statistic),
+    )
+    .and_then(|value| value.parse::<u64>().ok())
+    .unwrap_or_default()
+}
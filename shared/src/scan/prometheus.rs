@@ -0,0 +1,241 @@
+//! A parser of the Prometheus text exposition format.
+//!
+//! Only the samples are kept, `# HELP` and `# TYPE` lines are skipped like every other comment.
+//! Samples with a value of `NaN` or `±Inf` are skipped as well, since JSON cannot carry them.
+//! A malformed line is skipped and reported, so it does not hide the other samples.
+
+use crate::schemas::device_info::Metric;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    line: usize,
+    message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The samples of a text and the lines that could not be parsed
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub metrics: Vec<Metric>,
+    pub errors: Vec<ParseError>,
+}
+
+/// Parse every sample of the text
+pub fn parse(text: &str) -> Parsed {
+    let mut parsed = Parsed::default();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_sample(line) {
+            Ok(metric) if metric.value.is_finite() => parsed.metrics.push(metric),
+            Ok(_) => {}
+            Err(message) => parsed.errors.push(ParseError {
+                line: index + 1,
+                message: message.to_string(),
+            }),
+        }
+    }
+    parsed
+}
+
+/// `name{label="value",...} value [timestamp]`
+fn parse_sample(line: &str) -> Result<Metric, &'static str> {
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or("missing value")?;
+    let name = &line[..name_end];
+    if !is_metric_name(name) {
+        return Err("invalid metric name");
+    }
+
+    let mut rest = &line[name_end..];
+    let mut labels = BTreeMap::new();
+    if let Some(label_text) = rest.strip_prefix('{') {
+        rest = parse_labels(label_text, &mut labels)?;
+    }
+
+    let mut fields = rest.split_whitespace();
+    let value = fields.next().ok_or("missing value")?;
+    let value = match value {
+        "+Inf" | "Inf" => f64::INFINITY,
+        "-Inf" => f64::NEG_INFINITY,
+        "NaN" => f64::NAN,
+        value => value.parse::<f64>().map_err(|_| "invalid value")?,
+    };
+    // the timestamp is optional and not used
+    if let Some(timestamp) = fields.next() {
+        timestamp.parse::<i64>().map_err(|_| "invalid timestamp")?;
+    }
+    if fields.next().is_some() {
+        return Err("unexpected text after the timestamp");
+    }
+
+    Ok(Metric {
+        name: name.to_string(),
+        labels,
+        value,
+    })
+}
+
+/// Parse the labels up to the closing brace and return the text after it
+fn parse_labels<'a>(
+    mut text: &'a str,
+    labels: &mut BTreeMap<String, String>,
+) -> Result<&'a str, &'static str> {
+    loop {
+        text = text.trim_start();
+        if let Some(rest) = text.strip_prefix('}') {
+            return Ok(rest);
+        }
+        let (name, rest) = text.split_once('=').ok_or("invalid label")?;
+        let name = name.trim();
+        if !is_label_name(name) {
+            return Err("invalid label name");
+        }
+        let rest = rest
+            .trim_start()
+            .strip_prefix('"')
+            .ok_or("label value is not quoted")?;
+
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        let end = loop {
+            match chars.next().ok_or("unterminated label value")? {
+                (index, '"') => break index,
+                (_, '\\') => match chars.next().ok_or("unterminated label value")?.1 {
+                    'n' => value.push('\n'),
+                    escaped => value.push(escaped),
+                },
+                (_, c) => value.push(c),
+            }
+        };
+        labels.insert(name.to_string(), value);
+
+        text = rest[end + 1..].trim_start();
+        if let Some(rest) = text.strip_prefix(',') {
+            text = rest;
+        } else if !text.starts_with('}') {
+            return Err("expected ',' or '}' after a label");
+        }
+    }
+}
+
+fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn samples_with_labels() {
+        let parsed = parse(
+            "# HELP backup_age_seconds Age of the last backup\n\
+             # TYPE backup_age_seconds gauge\n\
+             backup_age_seconds 3600\n\
+             \n\
+             queue_length{queue=\"mail\", priority=\"high\"} 12 1700000000000\n\
+             job:errors:rate5m{job=\"api\",} -0.5e-3\n",
+        );
+        assert!(parsed.errors.is_empty());
+        assert_eq!(
+            parsed.metrics,
+            vec![
+                Metric::new("backup_age_seconds", 3600.0),
+                Metric {
+                    name: String::from("queue_length"),
+                    labels: labels(&[("queue", "mail"), ("priority", "high")]),
+                    value: 12.0,
+                },
+                Metric {
+                    name: String::from("job:errors:rate5m"),
+                    labels: labels(&[("job", "api")]),
+                    value: -0.0005,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_label_values() {
+        let parsed = parse(r#"files{path="C:\\data\\\"new\"",note="a\nb, c}"} 1"#);
+        assert!(parsed.errors.is_empty());
+        assert_eq!(
+            parsed.metrics[0].labels,
+            labels(&[("path", "C:\\data\\\"new\""), ("note", "a\nb, c}")])
+        );
+    }
+
+    #[test]
+    fn values_json_cannot_carry_are_skipped() {
+        let parsed = parse("a NaN\nb +Inf\nc -Inf\nd Inf\ne 1\n");
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.metrics, vec![Metric::new("e", 1.0)]);
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let parsed = parse(
+            "valid_before 1\n\
+             1invalid_name 1\n\
+             missing_value\n\
+             missing_value_after_labels{a=\"b\"}\n\
+             invalid_value abc\n\
+             invalid_timestamp 1 soon\n\
+             trailing_text 1 2 3\n\
+             unquoted{a=b} 1\n\
+             unterminated{a=\"b} 1\n\
+             invalid_label{1a=\"b\"} 1\n\
+             missing_comma{a=\"b\" c=\"d\"} 1\n\
+             escape_at_the_end{a=\"\\\n\
+             {a=\"b\"} 1\n\
+             valid_after 2\n",
+        );
+        assert_eq!(
+            parsed.metrics,
+            vec![
+                Metric::new("valid_before", 1.0),
+                Metric::new("valid_after", 2.0)
+            ]
+        );
+        assert_eq!(
+            parsed
+                .errors
+                .iter()
+                .map(|error| error.line)
+                .collect::<Vec<usize>>(),
+            (2..=13).collect::<Vec<usize>>()
+        );
+        assert_eq!(parsed.errors[0].to_string(), "line 2: invalid metric name");
+    }
+}
@@ -0,0 +1,51 @@
+//! Hardware temperature sensors.
+
+use crate::scan::hardware::{read_dir_names, read_trimmed};
+use crate::schemas::device_info::TemperatureReading;
+use std::path::Path;
+
+/// The temperatures of the hwmon sensors known to `sysinfo`.
+/// Machines without hwmon drivers, e.g. many ARM boards, fall back to the thermal zones.
+pub(crate) fn temperatures(components: &sysinfo::Components) -> Vec<TemperatureReading> {
+    let mut readings = components
+        .list()
+        .iter()
+        .filter_map(|component| {
+            Some(TemperatureReading {
+                label: component.label().to_string(),
+                temperature: component.temperature()?,
+                critical: component.critical(),
+            })
+        })
+        .collect::<Vec<TemperatureReading>>();
+    if readings.is_empty() {
+        readings = thermal_zones();
+    }
+    readings.sort_by(|a, b| a.label.cmp(&b.label));
+    readings
+}
+
+fn thermal_zones() -> Vec<TemperatureReading> {
+    let thermal = Path::new("/sys/class/thermal");
+    read_dir_names(thermal)
+        .into_iter()
+        .filter(|name| name.starts_with("thermal_zone"))
+        .filter_map(|name| {
+            let base = thermal.join(&name);
+            // the temperatures are given in millidegree Celsius
+            let millidegree = |file: &str| {
+                read_trimmed(base.join(file)).and_then(|value| value.parse::<f32>().ok())
+            };
+            let critical = read_dir_names(&base)
+                .into_iter()
+                .filter(|file| file.starts_with("trip_point_") && file.ends_with("_type"))
+                .find(|file| read_trimmed(base.join(file)).as_deref() == Some("critical"))
+                .and_then(|file| millidegree(&file.replace("_type", "_temp")));
+            Some(TemperatureReading {
+                label: read_trimmed(base.join("type")).unwrap_or(name),
+                temperature: millidegree("temp")? / 1000.0,
+                critical: critical.map(|critical| critical / 1000.0),
+            })
+        })
+        .collect()
+}
@@ -1,22 +1,59 @@
-use crate::schemas::device_info::{MachineInfo, MachineUsage};
-use std::sync::{Mutex, OnceLock};
+use crate::config::node_config::NodeConfig;
+use crate::scan::collector::{
+    CgroupCollector, Collector, CpuCollector, DiskCollector, LoadCollector, MemoryCollector,
+    NetworkCollector, SensorCollector,
+};
+use crate::scan::custom_metrics::{ScriptCollector, TextfileCollector};
+use crate::scan::{cgroup, hardware};
+use crate::schemas::device_info::{MachineInfo, MachineUsage, ProcessInfo, Virtualization};
+use crate::schemas::manager_messages::ProcessSortKey;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use sysinfo::System;
+use tokio::sync::watch;
+use tracing::{error, warn};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct SystemInfo {
     system: &'static Mutex<sysinfo::System>,
-    network: &'static Mutex<sysinfo::Networks>,
+    users: &'static Mutex<sysinfo::Users>,
+    collectors: Arc<Mutex<Vec<Box<dyn Collector>>>>,
     machine_info: MachineInfo,
 }
 
 impl SystemInfo {
-    pub fn new() -> SystemInfo {
+    pub fn new(config: &NodeConfig) -> SystemInfo {
         let system = sys_info();
-        let network = network_info();
+        let users = user_info();
 
-        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        let sys = system.lock().unwrap();
 
-        let sys = sysinfo::System::new_all();
+        let virtualization = hardware::virtualization();
+        // inside a container, its own limits are what the node can actually use
+        let resource_limits = match virtualization {
+            Virtualization::Container { .. } => cgroup::own_limits(),
+            _ => None,
+        };
+        let total_memory = resource_limits
+            .as_ref()
+            .and_then(|limits| limits.memory_limit)
+            .map_or(sys.total_memory(), |limit| limit.min(sys.total_memory()));
+
+        let mut collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(CpuCollector::new(system)),
+            Box::new(MemoryCollector::new(system)),
+            Box::new(NetworkCollector::new(config.network.clone())),
+            Box::new(LoadCollector::new(system)),
+            Box::new(DiskCollector::new()),
+            Box::new(SensorCollector::new()),
+            Box::new(CgroupCollector::new(&virtualization)),
+        ];
+        if let Some(directory) = &config.metrics.textfile_directory {
+            collectors.push(Box::new(TextfileCollector::new(directory.clone())));
+        }
+        for script in config.metrics.scripts.iter() {
+            collectors.push(Box::new(ScriptCollector::start(script.clone())));
+        }
 
         let machine_info = MachineInfo {
             os: System::name().unwrap_or(String::from("OS name not found")),
@@ -25,63 +62,147 @@ impl SystemInfo {
             kernel_version: System::kernel_version()
                 .unwrap_or(String::from("Kernel version not found")),
             number_of_cpu: System::physical_core_count().unwrap_or(0),
+            number_of_logical_cpu: sys.cpus().len(),
             arch: System::cpu_arch(),
             brand: sys
                 .cpus()
                 .first()
                 .map(|cpu| cpu.brand().to_string())
                 .unwrap_or_default(),
+            total_memory,
+            memory_modules: hardware::memory_modules(),
+            disks: hardware::disks(),
+            gpus: hardware::gpus(),
+            network_interfaces: hardware::network_interfaces(
+                &sysinfo::Networks::new_with_refreshed_list(),
+            ),
+            machine_id: hardware::machine_id(),
+            system_vendor: hardware::dmi("sys_vendor"),
+            system_model: hardware::dmi("product_name"),
+            bios_version: hardware::dmi("bios_version"),
+            boot_time: System::boot_time(),
+            virtualization,
+            resource_limits,
         };
+        drop(sys);
 
         Self {
             system,
-            network,
+            users,
+            collectors: Arc::new(Mutex::new(collectors)),
             machine_info,
         }
     }
 
+    /// Register a collector that runs after the built-in collectors and the ones registered before.
+    pub fn register(&self, collector: Box<dyn Collector>) {
+        self.collectors.lock().unwrap().push(collector);
+    }
+
     pub fn get_machine_info(&self) -> &MachineInfo {
         &self.machine_info
     }
 
+    /// Run every collector in order.
+    /// A failing collector is logged and skipped, so the others still report their values.
     pub fn get_usage(&self) -> MachineUsage {
-        let mut sys_guard = self.system.lock().unwrap();
-        let mut network_guard = self.network.lock().unwrap();
-
-        let mut cpu_usage = Vec::with_capacity(self.machine_info.number_of_cpu);
-        let mut cpu_frequency = Vec::with_capacity(self.machine_info.number_of_cpu);
-        let mut network_down = 0;
-        let mut network_up = 0;
+        let mut usage = MachineUsage::default();
+        for collector in self.collectors.lock().unwrap().iter_mut() {
+            if let Err(e) = collector.collect(&mut usage) {
+                error!("Collector {} failed: {}", collector.name(), e);
+            }
+        }
+        if usage.metrics.len() > MAX_METRICS {
+            warn!(
+                "Dropping {} metrics above the limit of {}",
+                usage.metrics.len() - MAX_METRICS,
+                MAX_METRICS
+            );
+            usage.metrics.truncate(MAX_METRICS);
+        }
+        usage.sampled_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        usage
+    }
 
-        sys_guard.refresh_all();
-        network_guard.refresh(true);
+    /// Sample the usage every `interval` on a thread of its own, so the collectors never block the
+    /// async runtime. The receiver holds the latest sample, `None` until the first one is taken.
+    /// The thread stops once every receiver is dropped.
+    pub fn spawn_sampler(&self, interval: Duration) -> watch::Receiver<Option<MachineUsage>> {
+        let (sender, receiver) = watch::channel(None);
+        let system_info = self.clone();
+        std::thread::Builder::new()
+            .name(String::from("usage-sampler"))
+            .spawn(move || {
+                // the CPU usage is measured between two refreshes
+                std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+                while sender.send(Some(system_info.get_usage())).is_ok() {
+                    std::thread::sleep(interval);
+                }
+            })
+            .expect("failed to spawn the usage sampler");
+        receiver
+    }
+}
 
-        for cpu in sys_guard.cpus().iter() {
-            cpu_usage.push(cpu.cpu_usage());
-            cpu_frequency.push(cpu.frequency());
-        }
+/// The upper bound of the custom metrics, so the usage fits into a single datagram
+pub const MAX_METRICS: usize = 256;
 
-        for data in network_guard.list().values() {
-            network_down += data.received();
-            network_up += data.transmitted();
-        }
+/// The upper bound of a process listing, so the response fits into a single datagram
+pub const MAX_PROCESS_LIMIT: usize = 50;
+/// Command lines longer than this are truncated
+const MAX_COMMAND_LINE_LENGTH: usize = 256;
 
-        MachineUsage {
-            total_memory: sys_guard.total_memory(),
-            used_memory: sys_guard.used_memory(),
-            total_swap: sys_guard.total_swap(),
-            used_swap: sys_guard.used_swap(),
-            cpu_usage,
-            cpu_frequency,
-            network_up,
-            network_down,
+impl SystemInfo {
+    /// The top processes by CPU or memory usage.
+    /// The CPU usage is measured since the previous refresh of the processes.
+    pub fn get_processes(&self, limit: usize, sort_by: ProcessSortKey) -> Vec<ProcessInfo> {
+        let mut sys_guard = self.system.lock().unwrap();
+        let users_guard = self.users.lock().unwrap();
+
+        sys_guard.refresh_processes(sysinfo::ProcessesToUpdate::All, true);
+
+        let mut processes = sys_guard
+            .processes()
+            .values()
+            .filter(|process| process.thread_kind().is_none())
+            .map(|process| ProcessInfo {
+                pid: process.pid().as_u32(),
+                name: process.name().to_string_lossy().to_string(),
+                user: process
+                    .user_id()
+                    .and_then(|uid| users_guard.get_user_by_id(uid))
+                    .map(|user| user.name().to_string()),
+                command_line: process
+                    .cmd()
+                    .iter()
+                    .map(|arg| arg.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .chars()
+                    .take(MAX_COMMAND_LINE_LENGTH)
+                    .collect(),
+                cpu_usage: process.cpu_usage(),
+                memory: process.memory(),
+            })
+            .collect::<Vec<ProcessInfo>>();
+
+        match sort_by {
+            ProcessSortKey::Cpu => processes.sort_by(|a, b| b.cpu_usage.total_cmp(&a.cpu_usage)),
+            ProcessSortKey::Memory => {
+                processes.sort_by_key(|process| std::cmp::Reverse(process.memory))
+            }
         }
+        processes.truncate(limit.min(MAX_PROCESS_LIMIT));
+        processes
     }
 }
 
 impl Default for SystemInfo {
     fn default() -> Self {
-        Self::new()
+        Self::new(&NodeConfig::default())
     }
 }
 
@@ -91,7 +212,7 @@ fn sys_info() -> &'static Mutex<sysinfo::System> {
     SYS_INFO.get_or_init(|| Mutex::new(sysinfo::System::new_all()))
 }
 
-fn network_info() -> &'static Mutex<sysinfo::Networks> {
-    static NETWORK_INFO: OnceLock<Mutex<sysinfo::Networks>> = OnceLock::new();
-    NETWORK_INFO.get_or_init(|| Mutex::new(sysinfo::Networks::new_with_refreshed_list()))
+fn user_info() -> &'static Mutex<sysinfo::Users> {
+    static USER_INFO: OnceLock<Mutex<sysinfo::Users>> = OnceLock::new();
+    USER_INFO.get_or_init(|| Mutex::new(sysinfo::Users::new_with_refreshed_list()))
 }
@@ -1,18 +1,136 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct MachineInfo {
     pub os: String,
     pub os_version: String,
     pub host_name: String,
     pub kernel_version: String,
+    /// The number of physical cores
     pub number_of_cpu: usize,
+    /// The number of logical CPUs, `MachineUsage::cpu_usage` has one entry per logical CPU
+    #[serde(default)]
+    pub number_of_logical_cpu: usize,
     pub arch: String,
     pub brand: String,
+    /// Total RAM in bytes
+    #[serde(default)]
+    pub total_memory: u64,
+    #[serde(default)]
+    pub memory_modules: Vec<MemoryModuleInfo>,
+    #[serde(default)]
+    pub disks: Vec<DiskInfo>,
+    #[serde(default)]
+    pub gpus: Vec<GpuInfo>,
+    #[serde(default)]
+    pub network_interfaces: Vec<NetworkInterfaceInfo>,
+    /// The ID the OS generated at installation, e.g. `/etc/machine-id`
+    #[serde(default)]
+    pub machine_id: Option<String>,
+    #[serde(default)]
+    pub system_vendor: Option<String>,
+    #[serde(default)]
+    pub system_model: Option<String>,
+    #[serde(default)]
+    pub bios_version: Option<String>,
+    /// Unix timestamp in seconds
+    #[serde(default)]
+    pub boot_time: u64,
+    #[serde(default)]
+    pub virtualization: Virtualization,
+    /// The limits of the cgroup the node runs in, only set when it runs inside a container.
+    /// `total_memory` is capped at the memory limit in that case.
+    #[serde(default)]
+    pub resource_limits: Option<ResourceLimits>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+impl MachineInfo {
+    /// A copy without the fields that change while the machine stays the same, i.e. the boot time
+    /// and the IP addresses of the interfaces. Specs are compared in this form.
+    pub fn without_volatile_fields(&self) -> MachineInfo {
+        let mut machine_info = self.clone();
+        machine_info.boot_time = 0;
+        for interface in machine_info.network_interfaces.iter_mut() {
+            interface.ip_addresses.clear();
+        }
+        machine_info
+    }
+}
+
+/// The CPU and memory limits of a cgroup v2, `None` stands for unlimited
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceLimits {
+    /// Thousandths of a CPU, e.g. 1500 for one and a half CPUs
+    pub cpu_limit: Option<u64>,
+    /// Bytes
+    pub memory_limit: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryModuleInfo {
+    pub label: String,
+    /// Size in bytes
+    pub size: u64,
+    pub memory_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DiskInfo {
+    /// The kernel name of the block device, e.g. `sda` or `nvme0n1`
+    pub name: String,
+    pub model: Option<String>,
+    /// Size in bytes
+    pub size: u64,
+    /// `None` if the kernel does not know whether the disk is rotational
+    pub rotational: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GpuInfo {
+    pub pci_address: String,
+    pub vendor: String,
+    /// The PCI device ID, e.g. `0x2204`
+    pub device_id: String,
+    pub driver: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkInterfaceInfo {
+    pub name: String,
+    pub mac_address: String,
+    pub ip_addresses: Vec<String>,
+    /// Link speed in Mbit/s, `None` if the link is down or the driver does not report it
+    pub speed: Option<u64>,
+}
+
+/// Where the node is running.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Virtualization {
+    #[default]
+    BareMetal,
+    /// `hypervisor` is e.g. `KVM`, `VMware` or `Hyper-V` when it can be detected
+    VirtualMachine { hypervisor: Option<String> },
+    /// `runtime` is e.g. `docker`, `podman`, `kubernetes` or `lxc` when it can be detected
+    Container { runtime: Option<String> },
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct MachineUsage {
     pub total_memory: u64,
@@ -21,6 +139,180 @@ pub struct MachineUsage {
     pub used_swap: u64,
     pub cpu_usage: Vec<f32>,
     pub cpu_frequency: Vec<u64>,
+    /// Bytes received per second, summed over the interfaces in `interfaces`
     pub network_down: u64,
+    /// Bytes transmitted per second, summed over the interfaces in `interfaces`
     pub network_up: u64,
+    /// Virtual interfaces are excluded unless the node is configured otherwise
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceUsage>,
+    #[serde(default)]
+    pub load_average: LoadAverage,
+    /// Seconds since boot
+    #[serde(default)]
+    pub uptime: u64,
+    #[serde(default)]
+    pub process_count: usize,
+    #[serde(default)]
+    pub thread_count: usize,
+    #[serde(default)]
+    pub temperatures: Vec<TemperatureReading>,
+    #[serde(default)]
+    pub filesystems: Vec<FilesystemUsage>,
+    #[serde(default)]
+    pub disk_io: Vec<DiskIoUsage>,
+    /// The containers found in the cgroup v2 hierarchy of the node
+    #[serde(default)]
+    pub containers: Vec<ContainerUsage>,
+    /// The cgroup of the node itself when it runs inside a container.
+    /// `total_memory` and `used_memory` are taken from it in that case.
+    #[serde(default)]
+    pub own_container: Option<ContainerUsage>,
+    /// The CPUs the node can use when it runs inside a container, i.e. the `cpu.max` quota of its
+    /// cgroup and at most the logical CPUs of the host. `None` outside a container.
+    #[serde(default)]
+    pub effective_cpus: Option<f32>,
+    /// The CPU usage of the container of the node in percent of `effective_cpus`, `cpu_usage`
+    /// stays the one of the host
+    #[serde(default)]
+    pub effective_cpu_usage: Option<f32>,
+    /// The metrics published by custom collectors
+    #[serde(default)]
+    pub metrics: Vec<Metric>,
+    /// Unix timestamp in seconds of when the node sampled the usage
+    #[serde(default)]
+    pub sampled_at: u64,
+}
+
+/// A named value published by a collector, e.g. the depth of an application queue
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Metric {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+}
+
+impl Metric {
+    pub fn new(name: impl Into<String>, value: f64) -> Self {
+        Self {
+            name: name.into(),
+            labels: BTreeMap::new(),
+            value,
+        }
+    }
+
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// The 1, 5 and 15 minute load averages, always zero on Windows
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct TemperatureReading {
+    pub label: String,
+    /// Degree Celsius
+    pub temperature: f32,
+    /// The critical temperature in degree Celsius, if the sensor reports one
+    pub critical: Option<f32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FilesystemUsage {
+    pub mount_point: String,
+    pub device: String,
+    pub file_system: String,
+    /// Capacity in bytes
+    pub total_space: u64,
+    /// Bytes available to unprivileged users
+    pub available_space: u64,
+    /// Bytes not available to unprivileged users, including the reserved blocks
+    pub used_space: u64,
+}
+
+/// The throughput of a network interface, averaged over the time since the previous sample.
+/// The errors and drops are cumulative counters since the interface came up.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct InterfaceUsage {
+    pub name: String,
+    pub received_bytes_per_second: f64,
+    pub transmitted_bytes_per_second: f64,
+    pub received_packets_per_second: f64,
+    pub transmitted_packets_per_second: f64,
+    pub received_errors: u64,
+    pub transmitted_errors: u64,
+    pub received_dropped: u64,
+    pub transmitted_dropped: u64,
+}
+
+/// The throughput of a block device, averaged over the time since the previous sample
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DiskIoUsage {
+    pub device: String,
+    pub read_bytes_per_second: f64,
+    pub write_bytes_per_second: f64,
+    pub read_iops: f64,
+    pub write_iops: f64,
+}
+
+/// The usage of a container as accounted by its cgroup, the rates are averaged over the time since
+/// the previous sample.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerUsage {
+    /// The container ID as found in the cgroup name
+    pub id: String,
+    /// e.g. `docker`, `podman`, `containerd` or `cri-o`
+    pub runtime: String,
+    /// Whether the container is part of a Kubernetes pod
+    pub kubernetes: bool,
+    /// The path of the cgroup relative to the cgroup root
+    pub cgroup: String,
+    /// Percent of a single CPU, so it can exceed 100. It is not scaled by `cpu_limit`
+    pub cpu_usage: f32,
+    /// Percent of `cpu_limit`, `None` when the container has no CPU limit
+    #[serde(default)]
+    pub cpu_limit_usage: Option<f32>,
+    /// Bytes
+    pub memory_usage: u64,
+    #[serde(flatten)]
+    pub limits: ResourceLimits,
+    pub read_bytes_per_second: f64,
+    pub write_bytes_per_second: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    pub user: Option<String>,
+    /// Truncated, so a listing fits into a single datagram
+    pub command_line: String,
+    /// Percent of a single CPU, so it can exceed 100 for multithreaded processes
+    pub cpu_usage: f32,
+    /// Resident set size in bytes
+    pub memory: u64,
 }
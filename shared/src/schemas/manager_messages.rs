@@ -7,6 +7,8 @@ pub enum ManagerRequestSchema {
     Spec(SpecRequest),
     #[serde(rename = "usageOverview")]
     UsageOverview(UsageOverviewRequest),
+    #[serde(rename = "processes")]
+    Processes(ProcessesRequest),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -21,6 +23,26 @@ pub struct UsageOverviewRequest {
     sender_ip: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessesRequest {
+    sender_ip: String,
+    /// The number of processes to return
+    pub limit: usize,
+    #[serde(default)]
+    pub sort_by: ProcessSortKey,
+}
+
+/// The order of the top-N process listing
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum ProcessSortKey {
+    #[default]
+    Cpu,
+    Memory,
+}
+
 pub struct ManagerRequest {
     ip: String,
 }
@@ -42,4 +64,12 @@ impl ManagerRequest {
         });
         serde_json::to_string(&request).unwrap()
     }
+    pub fn processes_request_json(&self, limit: usize, sort_by: ProcessSortKey) -> String {
+        let request = ManagerRequestSchema::Processes(ProcessesRequest {
+            sender_ip: self.ip.clone(),
+            limit,
+            sort_by,
+        });
+        serde_json::to_string(&request).unwrap()
+    }
 }
@@ -1,15 +1,19 @@
-use crate::schemas::device_info::{MachineInfo, MachineUsage};
+use crate::schemas::device_info::{MachineInfo, MachineUsage, ProcessInfo};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
 use std::net::Ipv4Addr;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "response", rename_all = "camelCase")]
 pub enum ResponseSchema {
     #[serde(rename = "spec")]
-    Spec(SpecResponse),
+    Spec(Box<SpecResponse>),
     #[serde(rename = "usageOverview")]
-    UsageOverview(UsageOverviewResponse),
+    UsageOverview(Box<UsageOverviewResponse>),
+    #[serde(rename = "processes")]
+    Processes(Box<ProcessesResponse>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -17,6 +21,14 @@ pub enum ResponseSchema {
 pub struct SpecResponse {
     pub ip: Ipv4Addr,
     pub spec: MachineInfo,
+    /// Labels declared in the node configuration
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// A fingerprint of the fields above, also sent with every usage
+    #[serde(default)]
+    pub spec_version: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -24,18 +36,90 @@ pub struct SpecResponse {
 pub struct UsageOverviewResponse {
     pub ip: Ipv4Addr,
     pub usage: MachineUsage,
+    /// The `spec_version` of the current spec, the manager asks for the spec again when it changes
+    #[serde(default)]
+    pub spec_version: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessesResponse {
+    pub ip: Ipv4Addr,
+    pub processes: Vec<ProcessInfo>,
 }
 
 impl SpecResponse {
-    pub fn spec_response_json(ip: Ipv4Addr, spec: MachineInfo) -> String {
-        let response = ResponseSchema::Spec(SpecResponse { ip, spec });
+    pub fn new(
+        ip: Ipv4Addr,
+        spec: MachineInfo,
+        labels: BTreeMap<String, String>,
+        description: Option<String>,
+    ) -> Self {
+        // a reboot or a new IP address alone does not make the manager ask for the spec again
+        let mut hasher = std::hash::DefaultHasher::new();
+        json!({ "spec": spec.without_volatile_fields(), "labels": labels, "description": description })
+            .to_string()
+            .hash(&mut hasher);
+        Self {
+            ip,
+            spec,
+            labels,
+            description,
+            spec_version: hasher.finish(),
+        }
+    }
+
+    pub fn spec_response_json(&self) -> String {
+        let response = ResponseSchema::Spec(Box::new(self.clone()));
         json!(response).to_string()
     }
 }
 
 impl UsageOverviewResponse {
-    pub fn usage_overview_response_json(ip: Ipv4Addr, usage: MachineUsage) -> String {
-        let response = ResponseSchema::UsageOverview(UsageOverviewResponse { ip, usage });
+    pub fn usage_overview_response_json(
+        ip: Ipv4Addr,
+        usage: MachineUsage,
+        spec_version: u64,
+    ) -> String {
+        let response = ResponseSchema::UsageOverview(Box::new(UsageOverviewResponse {
+            ip,
+            usage,
+            spec_version,
+        }));
         json!(response).to_string()
     }
 }
+
+impl ProcessesResponse {
+    pub fn processes_response_json(ip: Ipv4Addr, processes: Vec<ProcessInfo>) -> String {
+        let response = ResponseSchema::Processes(Box::new(ProcessesResponse { ip, processes }));
+        json!(response).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::machine_info;
+
+    #[test]
+    fn spec_version_ignores_the_volatile_fields() {
+        let ip = Ipv4Addr::new(10, 0, 0, 5);
+        let spec = machine_info("web-1", "Debian GNU/Linux", "x86_64");
+        let version = |spec: &MachineInfo| {
+            SpecResponse::new(ip, spec.clone(), BTreeMap::new(), None).spec_version
+        };
+
+        let rebooted = MachineInfo {
+            boot_time: 1_700_000_000,
+            ..spec.clone()
+        };
+        assert_eq!(version(&spec), version(&rebooted));
+
+        let upgraded = MachineInfo {
+            os_version: String::from("2.0"),
+            ..spec.clone()
+        };
+        assert_ne!(version(&spec), version(&upgraded));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod manager_handle;
 pub mod manager_server;
+pub mod manager_stats;
 pub(crate) mod manager_threads;
+pub mod request_guard;
 pub mod target_server;
@@ -0,0 +1,110 @@
+//! A handle to send commands to a running `ManagerServer`.
+//!
+//! The front ends, such as the web server and the native app, use it to ask nodes for data on
+//! demand instead of waiting for the next poll.
+
+use crate::commands::DiscoveryCommand;
+use crate::schemas::device_info::ProcessInfo;
+use crate::schemas::manager_messages::ProcessSortKey;
+use crate::schemas::target_messages::ResponseSchema;
+use crate::server::manager_stats::{ManagerCounters, ManagerStats};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for a node to answer an on-demand request
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManagerError {
+    /// The manager server is not running anymore
+    Closed,
+    /// The node did not answer in time
+    Timeout,
+}
+
+impl std::fmt::Display for ManagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManagerError::Closed => write!(f, "the manager server is not running"),
+            ManagerError::Timeout => write!(f, "the node did not answer in time"),
+        }
+    }
+}
+
+impl std::error::Error for ManagerError {}
+
+#[derive(Clone)]
+pub struct ManagerHandle {
+    command_tx: tokio::sync::mpsc::Sender<DiscoveryCommand>,
+    response_tx: tokio::sync::broadcast::Sender<ResponseSchema>,
+    stats: Arc<ManagerStats>,
+}
+
+impl ManagerHandle {
+    pub(crate) fn new(
+        command_tx: tokio::sync::mpsc::Sender<DiscoveryCommand>,
+        response_tx: tokio::sync::broadcast::Sender<ResponseSchema>,
+        stats: Arc<ManagerStats>,
+    ) -> Self {
+        Self {
+            command_tx,
+            response_tx,
+            stats,
+        }
+    }
+
+    /// The health counters of the manager server
+    pub fn counters(&self) -> ManagerCounters {
+        self.stats.counters()
+    }
+
+    /// Ask a node for its machine info again, the answer updates the data store.
+    pub async fn refresh(&self, ip: Ipv4Addr) -> Result<(), ManagerError> {
+        self.command_tx
+            .send(DiscoveryCommand::DeviceInformation(ip))
+            .await
+            .map_err(|_| ManagerError::Closed)
+    }
+
+    /// Broadcast the usage request now, new nodes are found without waiting for the next poll.
+    pub async fn scan(&self) -> Result<(), ManagerError> {
+        self.command_tx
+            .send(DiscoveryCommand::Scan)
+            .await
+            .map_err(|_| ManagerError::Closed)
+    }
+
+    /// Ask a node for its top processes and wait for the answer.
+    pub async fn processes(
+        &self,
+        ip: Ipv4Addr,
+        limit: usize,
+        sort_by: ProcessSortKey,
+    ) -> Result<Vec<ProcessInfo>, ManagerError> {
+        // subscribe before sending, so the response cannot be missed
+        let mut response_rx = self.response_tx.subscribe();
+
+        self.command_tx
+            .send(DiscoveryCommand::Processes { ip, limit, sort_by })
+            .await
+            .map_err(|_| ManagerError::Closed)?;
+
+        let wait_for_response = async {
+            loop {
+                match response_rx.recv().await {
+                    Ok(ResponseSchema::Processes(response)) if response.ip == ip => {
+                        return Ok(response.processes);
+                    }
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                        return Err(ManagerError::Closed);
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(RESPONSE_TIMEOUT, wait_for_response)
+            .await
+            .map_err(|_| ManagerError::Timeout)?
+    }
+}
@@ -1,18 +1,21 @@
 use crate::commands::DiscoveryCommand;
-use crate::store::data_store::DataStore;
+use crate::server::manager_stats::ManagerStats;
+use crate::store::data_store::DataStoreType;
+use std::sync::Arc;
 use tracing::error;
 
 pub struct ManagerServer {
-    data_store: std::sync::Arc<tokio::sync::RwLock<DataStore>>,
+    data_store: DataStoreType,
     command_tx: tokio::sync::mpsc::Sender<DiscoveryCommand>,
     command_rx: tokio::sync::mpsc::Receiver<DiscoveryCommand>,
     response_tx: tokio::sync::broadcast::Sender<crate::schemas::target_messages::ResponseSchema>,
     #[allow(dead_code)]
     response_rx: tokio::sync::broadcast::Receiver<crate::schemas::target_messages::ResponseSchema>,
+    stats: Arc<ManagerStats>,
 }
 
 impl ManagerServer {
-    pub fn new(date_store: std::sync::Arc<tokio::sync::RwLock<DataStore>>) -> Self {
+    pub fn new(date_store: DataStoreType) -> Self {
         // channel for commands
         let (command_tx, command_rx): (
             tokio::sync::mpsc::Sender<DiscoveryCommand>,
@@ -35,9 +38,19 @@ impl ManagerServer {
             response_tx,
             // The channel to receive responses, this is a broadcast channel
             response_rx,
+            stats: Arc::new(ManagerStats::default()),
         }
     }
 
+    /// A handle to send commands to this server once it is running
+    pub fn handle(&self) -> crate::server::manager_handle::ManagerHandle {
+        crate::server::manager_handle::ManagerHandle::new(
+            self.command_tx.clone(),
+            self.response_tx.clone(),
+            self.stats.clone(),
+        )
+    }
+
     pub async fn run(self) {
         // List to hold thread handlers
         let mut handlers = vec![];
@@ -46,7 +59,7 @@ impl ManagerServer {
 
         // manager server
         let discovery_server =
-            crate::server::manager_threads::discovery_server::DiscoveryServer::new();
+            crate::server::manager_threads::discovery_server::DiscoveryServer::new(self.stats);
         let response_rx = self.response_tx.subscribe();
         let discover_server_handler = tokio::spawn(async move {
             discovery_server
@@ -56,9 +69,13 @@ impl ManagerServer {
         handlers.push(discover_server_handler);
 
         // node_server
-        let node_server = crate::server::target_server::TargetServer::new();
+        let node_server = crate::server::target_server::TargetServer::new(
+            crate::config::node_config::NodeConfig::load(),
+        );
         let node_server_handler = tokio::spawn(async move {
-            node_server.run().await.expect("TODO: panic message");
+            if let Err(e) = node_server.run().await {
+                error!("The node server stopped: {}", e);
+            }
         });
         handlers.push(node_server_handler);
 
@@ -0,0 +1,60 @@
+//! Health counters of a running `ManagerServer`.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters shared between the threads of the manager, they only ever increase.
+#[derive(Debug, Default)]
+pub struct ManagerStats {
+    polls_sent: AtomicU64,
+    requests_sent: AtomicU64,
+    send_failures: AtomicU64,
+    replies_parsed: AtomicU64,
+    parse_failures: AtomicU64,
+}
+
+/// A copy of the counters at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagerCounters {
+    /// Usage requests broadcast to every node
+    pub polls_sent: u64,
+    /// Requests sent to a single node, such as spec and process requests
+    pub requests_sent: u64,
+    pub send_failures: u64,
+    pub replies_parsed: u64,
+    /// Replies that could not be parsed
+    pub parse_failures: u64,
+}
+
+impl ManagerStats {
+    pub(crate) fn poll_sent(&self) {
+        self.polls_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_sent(&self) {
+        self.requests_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn send_failed(&self) {
+        self.send_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reply_parsed(&self) {
+        self.replies_parsed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn parse_failed(&self) {
+        self.parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counters(&self) -> ManagerCounters {
+        ManagerCounters {
+            polls_sent: self.polls_sent.load(Ordering::Relaxed),
+            requests_sent: self.requests_sent.load(Ordering::Relaxed),
+            send_failures: self.send_failures.load(Ordering::Relaxed),
+            replies_parsed: self.replies_parsed.load(Ordering::Relaxed),
+            parse_failures: self.parse_failures.load(Ordering::Relaxed),
+        }
+    }
+}
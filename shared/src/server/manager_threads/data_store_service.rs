@@ -7,6 +7,9 @@ pub struct DataStoreService {
     data_store: DataStoreType,
 }
 
+/// Seconds without a usage update after which a node is marked offline
+const OFFLINE_THRESHOLD: u64 = 15;
+/// Seconds without a usage update after which a node is removed
 const THRESHOLD: u64 = 30;
 const CHECK_FREQUENCY: u64 = 10;
 
@@ -30,8 +33,8 @@ impl DataStoreService {
         });
 
         let ds_4_check = self.data_store.clone();
-        std::thread::spawn(async move || {
-            DataStoreService::check_lost_connection(ds_4_check.clone()).await
+        tokio::spawn(async move {
+            DataStoreService::check_lost_connection(ds_4_check).await;
         });
     }
 
@@ -48,29 +51,33 @@ impl DataStoreService {
                 Ok(res) => {
                     match res {
                         crate::schemas::target_messages::ResponseSchema::Spec(spec_response) => {
-                            let mut data_store = data_store.write().await;
-                            // TODO: to event
                             info!(
                                 "New node find: {:?} / {:?}",
                                 spec_response.ip, spec_response.spec.host_name
                             );
                             data_store
                                 .update_node_information(spec_response.ip, spec_response.spec);
+                            data_store.update_declared_labels(
+                                spec_response.ip,
+                                spec_response.labels,
+                                spec_response.description,
+                            );
+                            data_store
+                                .update_spec_version(spec_response.ip, spec_response.spec_version);
                         }
                         crate::schemas::target_messages::ResponseSchema::UsageOverview(
                             usage_response,
                         ) => {
-                            let mut lock = data_store.write().await;
-
-                            // get current node
-                            let node = lock.get_node(usage_response.ip);
+                            // check the current node
+                            let is_known = data_store.contains_node(usage_response.ip);
+                            let is_outdated = data_store
+                                .is_spec_outdated(usage_response.ip, usage_response.spec_version);
 
                             // write data
-                            lock.update_usage(usage_response.ip, usage_response.usage);
-                            drop(lock);
+                            data_store.update_usage(usage_response.ip, usage_response.usage);
 
-                            // if the current node is None, it means this is a new node
-                            if node.is_none()
+                            // a new node, or a node whose config or machine info changed
+                            if (!is_known || is_outdated)
                                 && let Err(e) = command_tx
                                     .send(crate::commands::DiscoveryCommand::DeviceInformation(
                                         usage_response.ip,
@@ -80,6 +87,8 @@ impl DataStoreService {
                                 error!("Failed to send Spec request: {}", e);
                             }
                         }
+                        // processes are answered to the requester, they are not stored
+                        crate::schemas::target_messages::ResponseSchema::Processes(_) => {}
                     }
                 }
                 Err(e) => {
@@ -94,26 +103,23 @@ impl DataStoreService {
     async fn check_lost_connection(data_store: DataStoreType) {
         loop {
             // read node
-            let lock = data_store.read().await;
-            let nodes = lock.get_node_status();
-            drop(lock);
+            let nodes = data_store.get_node_status();
 
             let now = SystemTime::now();
             let threshold = now.sub(Duration::from_secs(THRESHOLD));
+            let offline_threshold = now.sub(Duration::from_secs(OFFLINE_THRESHOLD));
 
+            // the nodes are a snapshot, so the age is checked again under the lock of each change
             for node in nodes.iter() {
-                if node.last_updated < threshold {
-                    let mut lock = data_store.write().await;
-                    lock.remove_node(&node.ip);
-                    drop(lock);
-
+                if data_store.remove_node_if_stale(&node.ip, threshold) {
                     let ip = &node.ip;
-                    // TODO to
                     let node_name = match &node.machine_info {
                         Some(info) => info.host_name.as_str(),
                         None => "No Name",
                     };
                     info!("The node removed: {:?} / {:?}", ip, node_name);
+                } else if data_store.mark_offline_if_stale(&node.ip, offline_threshold) {
+                    info!("The node is offline: {:?}", node.ip);
                 }
             }
 
@@ -1,5 +1,6 @@
 use crate::commands::DiscoveryCommand;
 use crate::schemas::target_messages::ResponseSchema;
+use crate::server::manager_stats::ManagerStats;
 use crate::utils::tools::get_ip;
 use std::sync::Arc;
 use tokio::net::UdpSocket;
@@ -7,11 +8,13 @@ use tracing::{debug, error, info};
 
 const BROADCAST_ADDRESS: &str = "255.255.255.255";
 
-pub struct DiscoveryServer {}
+pub struct DiscoveryServer {
+    stats: Arc<ManagerStats>,
+}
 
 impl DiscoveryServer {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(stats: Arc<ManagerStats>) -> Self {
+        Self { stats }
     }
     pub async fn run(
         &self,
@@ -39,8 +42,10 @@ impl DiscoveryServer {
 
         let command_socket = socket.clone();
         let command_request = request.clone();
+        let command_stats = self.stats.clone();
         tokio::task::spawn(async move {
             let spec_request = command_request.spec_request_json();
+            let usage_request = command_request.usage_overview_request_json();
             loop {
                 match command_rx.recv().await {
                     Some(command) => match command {
@@ -58,8 +63,54 @@ impl DiscoveryServer {
                                 .await
                             {
                                 error!("Failed to send Spec request: {}", e);
+                                command_stats.send_failed();
+                                continue;
+                            }
+                            command_stats.request_sent();
+                        }
+                        DiscoveryCommand::Scan => {
+                            debug!("Scan Request");
+                            if let Err(e) = command_socket
+                                .send_to(
+                                    usage_request.as_bytes(),
+                                    format!(
+                                        "{}:{}",
+                                        BROADCAST_ADDRESS,
+                                        crate::utils::constants::TARGET_PORT
+                                    ),
+                                )
+                                .await
+                            {
+                                error!("Failed to send Scan request: {}", e);
+                                command_stats.send_failed();
+                                continue;
+                            }
+                            command_stats.poll_sent();
+                        }
+                        DiscoveryCommand::Processes {
+                            ip: target_ip,
+                            limit,
+                            sort_by,
+                        } => {
+                            debug!("Processes Request: {:?}", target_ip);
+                            let processes_request =
+                                command_request.processes_request_json(limit, sort_by);
+                            if let Err(e) = command_socket
+                                .send_to(
+                                    processes_request.as_bytes(),
+                                    format!(
+                                        "{}:{}",
+                                        target_ip,
+                                        crate::utils::constants::TARGET_PORT
+                                    ),
+                                )
+                                .await
+                            {
+                                error!("Failed to send Processes request: {}", e);
+                                command_stats.send_failed();
                                 continue;
                             }
+                            command_stats.request_sent();
                         }
                     },
                     None => {
@@ -71,6 +122,7 @@ impl DiscoveryServer {
         });
 
         let usage_socket = socket.clone();
+        let usage_stats = self.stats.clone();
         tokio::spawn(async move {
             let usage_request = r.usage_overview_request_json();
             loop {
@@ -86,14 +138,17 @@ impl DiscoveryServer {
                     .await
                 {
                     error!("Failed to send Spec request: {}", e);
+                    usage_stats.send_failed();
                     break;
                 }
+                usage_stats.poll_sent();
                 tokio::time::sleep(std::time::Duration::from_secs(5)).await;
             }
         });
 
+        let reply_stats = self.stats.clone();
         tokio::spawn(async move {
-            let mut buf = [0; 1024];
+            let mut buf = vec![0; crate::utils::constants::MAX_DATAGRAM_SIZE];
 
             loop {
                 let (amt, src) = match socket.recv_from(&mut buf).await {
@@ -109,6 +164,7 @@ impl DiscoveryServer {
                 let Ok(received_data) = serde_json::from_slice::<
                     crate::schemas::target_messages::ResponseSchema,
                 >(received_data) else {
+                    reply_stats.parse_failed();
                     error!(
                         "Failed to parse received data from {}: {:?}",
                         src,
@@ -121,6 +177,7 @@ impl DiscoveryServer {
                     continue;
                 };
 
+                reply_stats.reply_parsed();
                 match received_data {
                     crate::schemas::target_messages::ResponseSchema::Spec(spec) => {
                         if let Err(e) = response_tx.send(ResponseSchema::Spec(spec)) {
@@ -132,6 +189,11 @@ impl DiscoveryServer {
                             error!("Failed to send Usage response: {}", e);
                         }
                     }
+                    crate::schemas::target_messages::ResponseSchema::Processes(processes) => {
+                        if let Err(e) = response_tx.send(ResponseSchema::Processes(processes)) {
+                            error!("Failed to send Processes response: {}", e);
+                        }
+                    }
                 }
             }
         });
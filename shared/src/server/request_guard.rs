@@ -0,0 +1,132 @@
+//! Protection of the `TargetServer` against abuse.
+//!
+//! A reply is far larger than a request, so a node answering everyone is an easy amplification
+//! target, and every request costs it a little CPU. The guard only lets requests from the allowed
+//! networks through, and at most as many per source as its token bucket allows.
+
+use crate::config::node_config::{AccessConfig, RateLimitConfig};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// The sources tracked at once, so spoofed source addresses cannot exhaust the memory.
+/// When every tracked source is still active, requests from new sources are dropped.
+const MAX_TRACKED_SOURCES: usize = 4096;
+
+/// The number of requests dropped since the node started, by reason
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedRequests {
+    /// The source is not in an allowed network
+    pub not_allowed: u64,
+    /// The source sent more requests than its rate limit
+    pub rate_limited: u64,
+    /// The request could not be parsed
+    pub malformed: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, config: &RateLimitConfig, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * config.requests_per_second).min(f64::from(config.burst));
+        self.updated_at = now;
+    }
+
+    /// Whether the bucket has refilled completely, so forgetting it changes nothing
+    fn is_full(&self, config: &RateLimitConfig, now: Instant) -> bool {
+        let mut bucket = *self;
+        bucket.refill(config, now);
+        bucket.tokens >= f64::from(config.burst)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RequestGuard {
+    config: AccessConfig,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    not_allowed: AtomicU64,
+    rate_limited: AtomicU64,
+    malformed: AtomicU64,
+}
+
+impl RequestGuard {
+    pub(crate) fn new(config: AccessConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            not_allowed: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            malformed: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether a request from the source should be answered, dropped requests are counted
+    pub(crate) fn admit(&self, source: IpAddr) -> bool {
+        if !self.is_allowed(source) {
+            self.not_allowed.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        if !self.take_token(source, Instant::now()) {
+            self.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn record_malformed(&self) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped(&self) -> DroppedRequests {
+        DroppedRequests {
+            not_allowed: self.not_allowed.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn is_allowed(&self, source: IpAddr) -> bool {
+        if self.config.allow.is_empty() {
+            return true;
+        }
+        match source {
+            IpAddr::V4(ip) => self.config.allow.iter().any(|cidr| cidr.contains(ip)),
+            IpAddr::V6(ip) => ip
+                .to_ipv4_mapped()
+                .is_some_and(|ip| self.config.allow.iter().any(|cidr| cidr.contains(ip))),
+        }
+    }
+
+    fn take_token(&self, source: IpAddr, now: Instant) -> bool {
+        let config = &self.config.rate_limit;
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(&source) && buckets.len() >= MAX_TRACKED_SOURCES {
+            buckets.retain(|_, bucket| !bucket.is_full(config, now));
+            if buckets.len() >= MAX_TRACKED_SOURCES {
+                return false;
+            }
+        }
+
+        let bucket = buckets.entry(source).or_insert(TokenBucket {
+            tokens: f64::from(config.burst),
+            updated_at: now,
+        });
+        bucket.refill(config, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -3,20 +3,45 @@
 //! This module defines the `TargetServer` struct, which listens for UDP requests from the manager,
 //! processes requests for system information and usage overview, and sends appropriate responses.
 
+use crate::config::node_config::NodeConfig;
+use crate::scan::collector::Collector;
 use crate::scan::usage;
 use crate::schemas;
+use crate::schemas::device_info::{MachineUsage, Metric};
+use crate::server::request_guard::{DroppedRequests, RequestGuard};
 use crate::utils::tools::get_ip;
+use std::net::Ipv4Addr;
+use std::time::Duration;
 use tokio::net::UdpSocket;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+/// The metric the node reports its dropped requests with, labelled by the `reason`
+pub const DROPPED_REQUESTS_METRIC: &str = "node_dropped_requests_total";
 
 pub struct TargetServer {
     system_info: usage::SystemInfo,
+    guard: RequestGuard,
+    config: NodeConfig,
 }
 
 impl TargetServer {
-    pub fn new() -> Self {
-        let system_info = usage::SystemInfo::default();
-        Self { system_info }
+    pub fn new(config: NodeConfig) -> Self {
+        let system_info = usage::SystemInfo::new(&config);
+        Self {
+            system_info,
+            guard: RequestGuard::new(config.access.clone()),
+            config,
+        }
+    }
+
+    /// The requests dropped by the allowlist and the rate limit, or because they were malformed
+    pub fn dropped_requests(&self) -> DroppedRequests {
+        self.guard.dropped()
+    }
+
+    /// Register a collector whose metrics are reported with the usage
+    pub fn register_collector(&self, collector: Box<dyn Collector>) {
+        self.system_info.register(collector);
     }
 
     pub async fn run(&self) -> std::io::Result<()> {
@@ -29,16 +54,39 @@ impl TargetServer {
         .await?;
         socket.set_broadcast(true)?;
         info!("Starting UDP server on {}", socket.local_addr()?);
+        self.serve(socket, ip).await
+    }
+
+    /// Answer the requests arriving at the socket, `ip` is the address reported to the manager
+    pub async fn serve(&self, socket: UdpSocket, ip: Ipv4Addr) -> std::io::Result<()> {
+        let usage = self
+            .system_info
+            .spawn_sampler(Duration::from_secs(self.config.sample_interval.max(1)));
 
-        let mut buf = [0; 1024];
+        // the machine info and the config do not change while the node runs
+        let spec = schemas::target_messages::SpecResponse::new(
+            ip,
+            self.system_info.get_machine_info().to_owned(),
+            self.config.labels.clone(),
+            self.config.description.clone(),
+        );
+        let spec_response = spec.spec_response_json();
+
+        let mut buf = vec![0; crate::utils::constants::MAX_DATAGRAM_SIZE];
 
         loop {
             let (amt, src) = socket.recv_from(&mut buf).await?;
             let received_data = &buf[..amt];
 
+            if !self.guard.admit(src.ip()) {
+                debug!("Dropped a request from {}", src);
+                continue;
+            }
+
             let Ok(request) = serde_json::from_slice::<
                 schemas::manager_messages::ManagerRequestSchema,
             >(received_data) else {
+                self.guard.record_malformed();
                 error!(
                     "Failed to parse received data from {}: {:?}",
                     src,
@@ -50,29 +98,113 @@ impl TargetServer {
             match request {
                 schemas::manager_messages::ManagerRequestSchema::Spec(req) => {
                     info!("Received Spec request from {}: {:?}", src, req);
-                    let response = schemas::target_messages::SpecResponse::spec_response_json(
-                        ip,
-                        self.system_info.get_machine_info().to_owned(),
-                    );
-                    debug!("Spec response: {:?}", response);
-                    socket.send_to(response.as_bytes(), src).await?;
+                    debug!("Spec response: {:?}", spec_response);
+                    if let Err(e) = socket.send_to(spec_response.as_bytes(), src).await {
+                        error!("Failed to send the spec response to {}: {}", src, e);
+                    }
                 }
                 schemas::manager_messages::ManagerRequestSchema::UsageOverview(req) => {
                     info!("Received Usage Overview request from {}: {:?}", src, req);
-                    let response = schemas::target_messages::UsageOverviewResponse::usage_overview_response_json(
-                        ip,
-                        self.system_info.get_usage().to_owned(),
-                    );
+                    let Some(mut snapshot) = usage.borrow().clone() else {
+                        debug!("No usage sampled yet, skipping the request from {}", src);
+                        continue;
+                    };
+                    let dropped = self.guard.dropped();
+                    for (reason, count) in [
+                        ("not_allowed", dropped.not_allowed),
+                        ("rate_limited", dropped.rate_limited),
+                        ("malformed", dropped.malformed),
+                    ] {
+                        snapshot.metrics.push(
+                            Metric::new(DROPPED_REQUESTS_METRIC, count as f64)
+                                .with_label("reason", reason),
+                        );
+                    }
+                    let Some(response) = usage_response_json(ip, snapshot, spec.spec_version)
+                    else {
+                        error!(
+                            "The usage does not fit into a datagram, skipping the request from {}",
+                            src
+                        );
+                        continue;
+                    };
                     debug!("usage response: {:?}", response);
-                    socket.send_to(response.as_bytes(), src).await?;
+                    if let Err(e) = socket.send_to(response.as_bytes(), src).await {
+                        error!("Failed to send the usage response to {}: {}", src, e);
+                    }
+                }
+                schemas::manager_messages::ManagerRequestSchema::Processes(req) => {
+                    info!("Received Processes request from {}: {:?}", src, req);
+                    // refreshing the processes blocks, so it must not run on the runtime thread
+                    let system_info = self.system_info.clone();
+                    let processes = tokio::task::spawn_blocking(move || {
+                        system_info.get_processes(req.limit, req.sort_by)
+                    })
+                    .await
+                    .unwrap_or_default();
+                    let response =
+                        schemas::target_messages::ProcessesResponse::processes_response_json(
+                            ip, processes,
+                        );
+                    debug!("processes response: {:?}", response);
+                    if let Err(e) = socket.send_to(response.as_bytes(), src).await {
+                        error!("Failed to send the processes response to {}: {}", src, e);
+                    }
                 }
             }
         }
     }
 }
 
+/// The usage response. While it does not fit into a datagram, the list taking the most space is
+/// halved, `None` when it does not fit even without the lists.
+fn usage_response_json(ip: Ipv4Addr, mut usage: MachineUsage, spec_version: u64) -> Option<String> {
+    fn size<T: serde::Serialize>(list: &[T]) -> usize {
+        serde_json::to_vec(list).map_or(0, |bytes| bytes.len())
+    }
+    fn halve<T>(list: &mut Vec<T>) {
+        list.truncate(list.len() / 2);
+    }
+    loop {
+        let response =
+            schemas::target_messages::UsageOverviewResponse::usage_overview_response_json(
+                ip,
+                usage.clone(),
+                spec_version,
+            );
+        if response.len() <= crate::utils::constants::MAX_DATAGRAM_SIZE {
+            return Some(response);
+        }
+        let sizes = [
+            size(&usage.metrics),
+            size(&usage.containers),
+            size(&usage.filesystems),
+            size(&usage.disk_io),
+            size(&usage.interfaces),
+            size(&usage.temperatures),
+        ];
+        let (largest, _) = sizes
+            .iter()
+            .enumerate()
+            .filter(|(_, size)| **size > 2)
+            .max_by_key(|(_, size)| **size)?;
+        warn!(
+            "The usage response of {} bytes exceeds a datagram, shortening a list",
+            response.len()
+        );
+        match largest {
+            0 => halve(&mut usage.metrics),
+            1 => halve(&mut usage.containers),
+            2 => halve(&mut usage.filesystems),
+            3 => halve(&mut usage.disk_io),
+            4 => halve(&mut usage.interfaces),
+            _ => halve(&mut usage.temperatures),
+        }
+    }
+}
+
 impl Default for TargetServer {
     fn default() -> Self {
-        Self::new()
+        Self::new(NodeConfig::default())
     }
 }
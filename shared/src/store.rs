@@ -1 +1,7 @@
 pub mod data_store;
+pub mod events;
+pub mod export;
+pub mod labels;
+pub mod node_query;
+pub mod spec_history;
+pub mod summary;
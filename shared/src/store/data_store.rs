@@ -1,8 +1,29 @@
 //! The data store of nodes.
+//!
+//! The nodes are spread over a fixed number of shards keyed by their IP address, each guarded by
+//! its own lock, so a usage update only blocks the readers and writers of the same shard.
+//! The usage records are reference counted, so readers only copy pointers while a lock is held
+//! and build the DTOs after releasing it.
 
+use crate::config::manager_config::ManagerConfig;
 use crate::schemas::device_info::{MachineInfo, MachineUsage};
+use crate::store::events::{DataStoreEvent, EVENT_CHANNEL_CAPACITY};
+use crate::store::export::{self, ExportError, ExportFormat, InventoryRow, UsageRow};
+use crate::store::labels::{LabelKey, LabelSelector, LabelSnapshot, LabelStore, NodeLabels};
+use crate::store::node_query::{CursorError, NodePage, NodeQuery};
+use crate::store::spec_history::{SpecHistory, SpecHistoryEntry};
+use crate::store::summary::{self, ChangedNode, FleetSummary, SummaryNode};
 use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+/// The number of shards the nodes are spread over.
+const SHARD_COUNT: usize = 16;
+/// The number of usage records kept per node.
+const USAGE_CAPACITY: usize = 5 * 100;
+
+type Shard = std::sync::RwLock<std::collections::HashMap<Ipv4Addr, Node>>;
 
 struct MachineUsageRecord {
     machine_usage: MachineUsage,
@@ -13,86 +34,158 @@ struct MachineUsageRecord {
 struct Node {
     ip: Ipv4Addr,
     machine_info: Option<MachineInfo>,
-    usage: std::collections::VecDeque<MachineUsageRecord>,
+    // declared in the node configuration
+    declared_labels: BTreeMap<String, String>,
+    description: Option<String>,
+    usage: std::collections::VecDeque<Arc<MachineUsageRecord>>,
+    state: NodeState,
+    // the fingerprint of the last spec response, `None` until it arrives
+    spec_version: Option<u64>,
+    // Unix timestamp in seconds of the first usage, reset when the node is removed and found again
+    first_seen: u64,
     last_updated: std::time::SystemTime,
 }
 
 impl Node {
     fn new(ip: Ipv4Addr, machine_info: Option<MachineInfo>, machine_usage: MachineUsage) -> Self {
-        let mut usage = std::collections::VecDeque::with_capacity(5 * 100);
+        let mut usage = std::collections::VecDeque::with_capacity(USAGE_CAPACITY);
         let machine_usage = MachineUsageRecord {
             machine_usage,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
+            timestamp: now_timestamp(),
         };
-        usage.push_front(machine_usage);
+        usage.push_front(Arc::new(machine_usage));
         Self {
             ip,
             machine_info,
+            declared_labels: BTreeMap::new(),
+            description: None,
             usage,
+            state: NodeState::Online,
+            spec_version: None,
+            first_seen: now_timestamp(),
             last_updated: std::time::SystemTime::now(),
         }
     }
 
     /// remove the first element from the usage queue, then push the new usage to the end
     fn update_usage(&mut self, machine_usage: MachineUsage) {
-        if self.usage.len() >= 500 {
+        if self.usage.len() >= USAGE_CAPACITY {
             self.usage.pop_back();
         }
         let machine_usage = MachineUsageRecord {
             machine_usage,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
+            timestamp: now_timestamp(),
         };
-        self.usage.push_front(machine_usage);
+        self.usage.push_front(Arc::new(machine_usage));
+        self.state = NodeState::Online;
         self.last_updated = std::time::SystemTime::now();
     }
 
     /// update the machine info
+    /// The usage queue is kept, the change itself is recorded in the spec history.
     fn update_info(&mut self, machine_info: MachineInfo) {
-        // if the machine info is different, reset the usage queue
-        if let Some(info) = &self.machine_info
-            && info != &machine_info
-        {
-            self.usage = std::collections::VecDeque::with_capacity(5 * 100);
-        }
         self.machine_info = Some(machine_info);
     }
 
-    fn to_node_data(&self) -> NodeData {
-        NodeData {
+    fn host_name(&self) -> Option<&str> {
+        self.machine_info
+            .as_ref()
+            .map(|machine_info| machine_info.host_name.as_str())
+    }
+
+    /// What the labels assigned to the node are stored under
+    fn label_key(&self) -> LabelKey {
+        let machine_id = self
+            .machine_info
+            .as_ref()
+            .and_then(|machine_info| machine_info.machine_id.as_deref());
+        LabelKey::new(self.ip, machine_id)
+    }
+
+    /// The labels declared by the node merged with the labels assigned by the manager.
+    /// The assigned labels take precedence, so the manager can override what a node declares.
+    fn labels(&self, label_store: &LabelStore) -> NodeLabels {
+        let assigned = label_store.get(&self.label_key());
+        let mut labels = self.declared_labels.clone();
+        labels.extend(assigned.labels);
+        NodeLabels {
+            labels,
+            groups: assigned.groups,
+        }
+    }
+
+    /// Take a cheap copy of the node to be converted after the lock is released.
+    fn snapshot(&self, label_store: &LabelStore) -> NodeSnapshot {
+        NodeSnapshot {
             ip: self.ip,
             machine_info: self.machine_info.clone(),
-            usage: self
-                .usage
-                .iter()
-                .map(|record| MachineUsageData {
-                    machine_usage: record.machine_usage.clone(),
-                    timestamp: record.timestamp,
-                })
-                .collect(),
+            labels: self.labels(label_store),
+            description: self.description.clone(),
+            usage: self.usage.iter().cloned().collect(),
+            state: self.state,
             last_updated: self.last_updated,
         }
     }
 
-    fn to_overview(&self) -> NodeOverview {
+    fn to_overview(&self, label_store: &LabelStore) -> NodeOverview {
         NodeOverview {
             ip: self.ip,
             machine_info: self.machine_info.clone(),
+            labels: self.labels(label_store),
+            description: self.description.clone(),
             usage: self
                 .usage
                 .front()
                 .map(|record| record.machine_usage.clone()),
+            state: self.state,
+            last_updated: self.last_updated,
+        }
+    }
+}
+
+/// A node whose usage records are shared with the data store.
+struct NodeSnapshot {
+    ip: Ipv4Addr,
+    machine_info: Option<MachineInfo>,
+    labels: NodeLabels,
+    description: Option<String>,
+    usage: Vec<Arc<MachineUsageRecord>>,
+    state: NodeState,
+    last_updated: std::time::SystemTime,
+}
+
+impl NodeSnapshot {
+    fn into_node_data(self) -> NodeData {
+        NodeData {
+            ip: self.ip,
+            machine_info: self.machine_info,
+            labels: self.labels,
+            description: self.description,
+            usage: self
+                .usage
+                .iter()
+                .map(|record| MachineUsageData {
+                    machine_usage: record.machine_usage.clone(),
+                    timestamp: record.timestamp,
+                })
+                .collect(),
+            state: self.state,
             last_updated: self.last_updated,
         }
     }
 }
 
+/// Whether a node is still answering the usage requests.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum NodeState {
+    Online,
+    Offline,
+}
+
 #[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct MachineUsageData {
     pub machine_usage: MachineUsage,
@@ -106,7 +199,12 @@ pub struct MachineUsageData {
 pub struct NodeData {
     pub ip: Ipv4Addr,
     pub machine_info: Option<MachineInfo>,
+    /// The declared and assigned labels merged, and the assigned groups
+    #[serde(flatten)]
+    pub labels: NodeLabels,
+    pub description: Option<String>,
     pub usage: Vec<MachineUsageData>,
+    pub state: NodeState,
     pub last_updated: std::time::SystemTime,
 }
 
@@ -116,75 +214,499 @@ pub struct NodeData {
 pub struct NodeOverview {
     pub ip: Ipv4Addr,
     pub machine_info: Option<MachineInfo>,
+    /// The declared and assigned labels merged, and the assigned groups
+    #[serde(flatten)]
+    pub labels: NodeLabels,
+    pub description: Option<String>,
     pub usage: Option<MachineUsage>,
+    pub state: NodeState,
     pub last_updated: std::time::SystemTime,
 }
 
-pub type DataStoreType = std::sync::Arc<tokio::sync::RwLock<DataStore>>;
+pub type DataStoreType = std::sync::Arc<DataStore>;
 
 pub struct DataStore {
-    nodes: std::sync::Arc<std::sync::RwLock<std::collections::HashMap<Ipv4Addr, Node>>>,
+    shards: Box<[Shard]>,
+    // kept apart from the nodes, so the history survives the removal of a node
+    spec_history: std::sync::RwLock<std::collections::HashMap<Ipv4Addr, SpecHistory>>,
+    // keyed by machine ID, so the labels survive IP changes
+    // Locked before a shard when both are needed.
+    labels: std::sync::RwLock<LabelStore>,
+    events: tokio::sync::broadcast::Sender<DataStoreEvent>,
 }
 
 impl DataStore {
     pub fn new() -> Self {
+        let (events, _) = tokio::sync::broadcast::channel(EVENT_CHANNEL_CAPACITY);
         Self {
-            nodes: std::sync::Arc::new(std::sync::RwLock::new(std::collections::HashMap::new())),
+            shards: (0..SHARD_COUNT)
+                .map(|_| std::sync::RwLock::new(std::collections::HashMap::new()))
+                .collect(),
+            spec_history: std::sync::RwLock::new(std::collections::HashMap::new()),
+            labels: std::sync::RwLock::new(LabelStore::default()),
+            events,
         }
     }
-    /// This method returns `DataStore` with Arc<RwLock<DataStore>>
+    /// This method returns `DataStore` with Arc<DataStore>
     pub fn init() -> DataStoreType {
-        std::sync::Arc::new(tokio::sync::RwLock::new(Self {
-            nodes: std::sync::Arc::new(std::sync::RwLock::new(std::collections::HashMap::new())),
-        }))
+        std::sync::Arc::new(Self::new())
+    }
+
+    /// This method returns `DataStore` with Arc<DataStore>, the labels are loaded from and
+    /// persisted to the configured label file
+    pub fn init_with_config(config: &ManagerConfig) -> DataStoreType {
+        let mut data_store = Self::new();
+        if let Some(label_file) = &config.label_file {
+            data_store.labels = std::sync::RwLock::new(LabelStore::load(label_file.clone()));
+        }
+        std::sync::Arc::new(data_store)
+    }
+
+    /// Subscribe to the change events of this data store.
+    /// Each subscriber receives every event published after it subscribed.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<DataStoreEvent> {
+        self.events.subscribe()
     }
 
     /// get nodes
     pub fn get_node_status(&self) -> std::vec::Vec<NodeOverview> {
-        let node_lock = self.nodes.read().unwrap();
-        node_lock
-            .values()
-            .map(|node| node.to_overview())
-            .collect::<std::vec::Vec<NodeOverview>>()
+        self.get_node_overview()
     }
 
     pub fn get_node(&self, ip: Ipv4Addr) -> Option<NodeData> {
-        let node_lock = self.nodes.read().unwrap();
-        node_lock.get(&ip).map(|node| node.to_node_data())
+        let snapshot = {
+            let label_lock = self.labels.read().unwrap();
+            let shard_lock = self.shard(&ip).read().unwrap();
+            shard_lock.get(&ip).map(|node| node.snapshot(&label_lock))
+        };
+        snapshot.map(|snapshot| snapshot.into_node_data())
     }
 
+    /// Whether the data store has a node with the given IP
+    pub fn contains_node(&self, ip: Ipv4Addr) -> bool {
+        self.shard(&ip).read().unwrap().contains_key(&ip)
+    }
+
+    /// get the spec history of a node from the oldest to the newest version
+    /// The history is kept even after the node has been removed.
+    pub fn get_spec_history(&self, ip: Ipv4Addr) -> Option<Vec<SpecHistoryEntry>> {
+        let history_lock = self.spec_history.read().unwrap();
+        history_lock.get(&ip).map(|history| history.entries())
+    }
+
+    /// The shards are locked one after another, never all at once.
     pub fn get_node_overview(&self) -> std::vec::Vec<NodeOverview> {
-        let node_lock = self.nodes.read().unwrap();
-        node_lock
-            .values()
-            .map(|node| node.to_overview())
-            .collect::<std::vec::Vec<NodeOverview>>()
+        self.filter_node_overview(&LabelSelector::default(), None)
+    }
+
+    /// get the nodes whose labels match the selector and that are members of the group if given
+    pub fn filter_node_overview(
+        &self,
+        selector: &LabelSelector,
+        group: Option<&str>,
+    ) -> std::vec::Vec<NodeOverview> {
+        let label_lock = self.labels.read().unwrap();
+        let mut overviews = Vec::new();
+        for shard in self.shards.iter() {
+            let shard_lock = shard.read().unwrap();
+            overviews.extend(
+                shard_lock
+                    .values()
+                    .filter(|node| {
+                        let labels = node.labels(&label_lock);
+                        selector.matches(&labels.labels)
+                            && group.is_none_or(|group| labels.groups.contains(group))
+                    })
+                    .map(|node| node.to_overview(&label_lock)),
+            );
+        }
+        overviews
+    }
+
+    /// get a page of the nodes matching the query in the order of its sort
+    /// Only the sort values of the matching nodes are collected, the overviews are built for the
+    /// nodes of the page alone.
+    pub fn query_nodes(&self, query: &NodeQuery) -> Result<NodePage, CursorError> {
+        query.validate()?;
+        let label_lock = self.labels.read().unwrap();
+        let mut entries = Vec::new();
+        for shard in self.shards.iter() {
+            let shard_lock = shard.read().unwrap();
+            entries.extend(
+                shard_lock
+                    .values()
+                    .filter(|node| {
+                        query.matches(
+                            node.ip,
+                            node.machine_info.as_ref(),
+                            &node.labels(&label_lock),
+                            node.description.as_deref(),
+                        )
+                    })
+                    .map(|node| {
+                        query.entry(
+                            node.ip,
+                            node.machine_info.as_ref(),
+                            node.usage.front().map(|record| &record.machine_usage),
+                            node.last_updated,
+                        )
+                    }),
+            );
+        }
+        let total = entries.len();
+        let more = query.page(&mut entries);
+
+        // a node removed in the meantime is left out of the page
+        let nodes = entries
+            .iter()
+            .filter_map(|entry| {
+                let shard_lock = self.shard(&entry.ip()).read().unwrap();
+                shard_lock
+                    .get(&entry.ip())
+                    .map(|node| node.to_overview(&label_lock))
+            })
+            .collect();
+        let next_cursor = match entries.pop() {
+            Some(last) if more => Some(query.cursor_after(last).to_string()),
+            _ => None,
+        };
+        Ok(NodePage {
+            nodes,
+            total,
+            next_cursor,
+        })
+    }
+
+    /// summarize the fleet, optionally grouped by the value of a label
+    /// `changed_since` is the Unix timestamp in seconds after which a recorded machine info counts
+    /// as a recent change.
+    pub fn summary(&self, group_by: Option<&str>, changed_since: u64) -> FleetSummary {
+        // the usage records are collected apart, so the nodes can borrow them after the locks
+        // are released
+        let mut records = Vec::new();
+        let mut nodes = Vec::new();
+        {
+            let label_lock = self.labels.read().unwrap();
+            for shard in self.shards.iter() {
+                let shard_lock = shard.read().unwrap();
+                for node in shard_lock.values() {
+                    let info = node.machine_info.as_ref();
+                    records.push(node.usage.front().cloned());
+                    nodes.push(SummaryNode {
+                        ip: node.ip,
+                        host_name: info.map(|info| info.host_name.clone()),
+                        os: info.map(|info| info.os.clone()),
+                        arch: info.map(|info| info.arch.clone()),
+                        state: node.state,
+                        usage: None,
+                        group: group_by.and_then(|key| node.labels(&label_lock).labels.remove(key)),
+                    });
+                }
+            }
+        }
+        for (node, record) in nodes.iter_mut().zip(records.iter()) {
+            node.usage = record.as_ref().map(|record| &record.machine_usage);
+        }
+
+        let recently_changed = {
+            let history_lock = self.spec_history.read().unwrap();
+            history_lock
+                .iter()
+                .filter_map(|(ip, history)| {
+                    let latest = history.latest()?;
+                    (latest.timestamp >= changed_since).then(|| ChangedNode {
+                        ip: *ip,
+                        host_name: latest.machine_info.host_name.clone(),
+                        version: latest.version,
+                        timestamp: latest.timestamp,
+                        changed_fields: latest
+                            .changes
+                            .iter()
+                            .map(|change| change.field.clone())
+                            .collect(),
+                    })
+                })
+                .collect()
+        };
+
+        summary::summarize(&nodes, recently_changed, group_by.is_some())
+    }
+
+    /// Write the current inventory, one row per node ordered by the IP address
+    pub fn export_inventory(
+        &self,
+        format: ExportFormat,
+        writer: impl std::io::Write,
+    ) -> Result<(), ExportError> {
+        let mut rows = Vec::new();
+        {
+            let label_lock = self.labels.read().unwrap();
+            for shard in self.shards.iter() {
+                let shard_lock = shard.read().unwrap();
+                rows.extend(shard_lock.values().map(|node| {
+                    InventoryRow::new(
+                        node.ip,
+                        node.machine_info.as_ref(),
+                        node.labels(&label_lock),
+                        node.first_seen,
+                        unix_timestamp(node.last_updated),
+                    )
+                }));
+            }
+        }
+        rows.sort_unstable_by_key(|row| row.ip);
+        export::write(&rows, format, writer)
+    }
+
+    /// Write the usage records taken between `from` and `to`, both Unix timestamps in seconds
+    /// and inclusive, ordered by the IP address and then by the time.
+    /// Only the records still kept in the data store are written.
+    pub fn export_usage(
+        &self,
+        from: u64,
+        to: u64,
+        format: ExportFormat,
+        writer: impl std::io::Write,
+    ) -> Result<(), ExportError> {
+        let mut records = Vec::new();
+        for shard in self.shards.iter() {
+            let shard_lock = shard.read().unwrap();
+            for node in shard_lock.values() {
+                records.extend(
+                    node.usage
+                        .iter()
+                        .filter(|record| (from..=to).contains(&record.timestamp))
+                        .map(|record| {
+                            (node.ip, node.host_name().map(String::from), record.clone())
+                        }),
+                );
+            }
+        }
+        records.sort_unstable_by_key(|(ip, _, record)| (*ip, record.timestamp));
+        let rows = records
+            .into_iter()
+            .map(|(ip, host_name, record)| {
+                UsageRow::new(ip, host_name, record.timestamp, &record.machine_usage)
+            })
+            .collect::<Vec<_>>();
+        export::write(&rows, format, writer)
+    }
+
+    /// Replace the labels assigned to a node, the labels declared by the node are kept.
+    /// Returns `None` if the node is unknown.
+    pub fn set_labels(&self, ip: Ipv4Addr, labels: BTreeMap<String, String>) -> Option<NodeLabels> {
+        self.update_labels(ip, |label_store, key| label_store.set_labels(key, labels))
+    }
+
+    /// Replace the groups of a node.
+    /// Returns `None` if the node is unknown.
+    pub fn set_groups(&self, ip: Ipv4Addr, groups: BTreeSet<String>) -> Option<NodeLabels> {
+        self.update_labels(ip, |label_store, key| label_store.set_groups(key, groups))
+    }
+
+    /// Update the labels assigned to a node, then write the label file after the lock is released
+    fn update_labels(
+        &self,
+        ip: Ipv4Addr,
+        update: impl FnOnce(&mut LabelStore, LabelKey) -> NodeLabels,
+    ) -> Option<NodeLabels> {
+        let (labels, snapshot) = {
+            let mut label_lock = self.labels.write().unwrap();
+            let key = self.shard(&ip).read().unwrap().get(&ip)?.label_key();
+            let labels = update(&mut label_lock, key);
+            (labels, label_lock.snapshot())
+        };
+        persist_labels(snapshot);
+        Some(labels)
+    }
+
+    /// get the IP addresses of the known members of every group
+    pub fn get_groups(&self) -> BTreeMap<String, BTreeSet<Ipv4Addr>> {
+        let label_lock = self.labels.read().unwrap();
+        let mut groups: BTreeMap<String, BTreeSet<Ipv4Addr>> = BTreeMap::new();
+        for shard in self.shards.iter() {
+            let shard_lock = shard.read().unwrap();
+            for node in shard_lock.values() {
+                for group in label_lock.get(&node.label_key()).groups {
+                    groups.entry(group).or_default().insert(node.ip);
+                }
+            }
+        }
+        groups
     }
 
     /// Add or update a node's data
-    pub fn update_usage(&mut self, ip: Ipv4Addr, machine_usage: MachineUsage) {
-        let mut node_lock = self.nodes.write().unwrap();
+    pub fn update_usage(&self, ip: Ipv4Addr, machine_usage: MachineUsage) {
+        let timestamp = now_timestamp();
+        // the copy for the event is only made when someone listens
+        let event_usage =
+            (self.events.receiver_count() > 0).then(|| Box::new(machine_usage.clone()));
 
-        node_lock
-            .entry(ip)
-            .and_modify(|node| node.update_usage(machine_usage.clone()))
-            .or_insert_with(|| Node::new(ip, None, machine_usage));
+        let discovered = {
+            let mut shard_lock = self.shard(&ip).write().unwrap();
+            match shard_lock.get_mut(&ip) {
+                Some(node) => {
+                    node.update_usage(machine_usage);
+                    false
+                }
+                None => {
+                    shard_lock.insert(ip, Node::new(ip, None, machine_usage));
+                    true
+                }
+            }
+        };
+
+        if discovered {
+            self.publish(DataStoreEvent::NodeDiscovered { ip, timestamp });
+        }
+        if let Some(usage) = event_usage {
+            self.publish(DataStoreEvent::UsageUpdated {
+                ip,
+                usage,
+                timestamp,
+            });
+        }
     }
 
     /// Add the machine info to the node
     /// If there is no node with the given IP, do nothing
-    pub fn update_node_information(&mut self, ip: Ipv4Addr, machine_info: MachineInfo) {
-        let mut node_lock = self.nodes.write().unwrap();
+    pub fn update_node_information(&self, ip: Ipv4Addr, machine_info: MachineInfo) {
+        // the labels move along with the key, so no reader sees the node without them
+        let mut label_lock = self.labels.write().unwrap();
+        let mut shard_lock = self.shard(&ip).write().unwrap();
+
+        let Some(node) = shard_lock.get_mut(&ip) else {
+            return;
+        };
+        let previous = node.machine_info.clone();
+        if previous.as_ref() == Some(&machine_info) {
+            return;
+        }
+        node.update_info(machine_info.clone());
+        drop(shard_lock);
+        let snapshot = match &machine_info.machine_id {
+            Some(machine_id) if label_lock.move_to_machine(ip, machine_id) => label_lock.snapshot(),
+            _ => None,
+        };
+        drop(label_lock);
+        persist_labels(snapshot);
+
+        let timestamp = now_timestamp();
+        let changes = self
+            .spec_history
+            .write()
+            .unwrap()
+            .entry(ip)
+            .or_default()
+            .record(&machine_info, timestamp)
+            .unwrap_or_default();
+        self.publish(DataStoreEvent::NodeSpecChanged {
+            ip,
+            previous: previous.map(Box::new),
+            current: Box::new(machine_info),
+            changes,
+            timestamp,
+        });
+    }
+
+    /// Replace the labels and the description declared by the node itself
+    /// If there is no node with the given IP, do nothing
+    pub fn update_declared_labels(
+        &self,
+        ip: Ipv4Addr,
+        labels: BTreeMap<String, String>,
+        description: Option<String>,
+    ) {
+        let mut shard_lock = self.shard(&ip).write().unwrap();
+
+        if let Some(node) = shard_lock.get_mut(&ip) {
+            node.declared_labels = labels;
+            node.description = description;
+        }
+    }
 
-        if let Some(node) = node_lock.get_mut(&ip) {
-            node.update_info(machine_info);
+    /// Remember the fingerprint of the spec response a node sent last
+    pub fn update_spec_version(&self, ip: Ipv4Addr, spec_version: u64) {
+        if let Some(node) = self.shard(&ip).write().unwrap().get_mut(&ip) {
+            node.spec_version = Some(spec_version);
         }
     }
 
+    /// Whether a node reported a spec other than the one stored, e.g. after a change of its
+    /// config. `false` for unknown nodes and while the first spec is awaited.
+    pub fn is_spec_outdated(&self, ip: Ipv4Addr, spec_version: u64) -> bool {
+        self.shard(&ip)
+            .read()
+            .unwrap()
+            .get(&ip)
+            .and_then(|node| node.spec_version)
+            .is_some_and(|known| known != spec_version)
+    }
+
+    /// Mark a node as offline if it was last updated before `cutoff`.
+    /// The age is checked under the lock that marks the node, so a usage update that arrived in
+    /// the meantime keeps it online. The node is kept until it is removed, and it is back online
+    /// with the next usage update. Returns whether the node was marked.
+    pub fn mark_offline_if_stale(&self, ip: &Ipv4Addr, cutoff: std::time::SystemTime) -> bool {
+        let mut shard_lock = self.shard(ip).write().unwrap();
+
+        let Some(node) = shard_lock.get_mut(ip) else {
+            return false;
+        };
+        if node.state != NodeState::Online || node.last_updated >= cutoff {
+            return false;
+        }
+        node.state = NodeState::Offline;
+        drop(shard_lock);
+        self.publish(DataStoreEvent::NodeOffline {
+            ip: *ip,
+            timestamp: now_timestamp(),
+        });
+        true
+    }
+
     /// Remove a node from the data store
-    pub fn remove_node(&mut self, ip: &Ipv4Addr) {
-        let mut node_lock = self.nodes.write().unwrap();
-        node_lock.remove(ip);
+    /// Returns whether the node was known
+    pub fn remove_node(&self, ip: &Ipv4Addr) -> bool {
+        let removed = self.shard(ip).write().unwrap().remove(ip);
+        if removed.is_some() {
+            self.publish_removed(ip);
+        }
+        removed.is_some()
+    }
+
+    /// Remove a node if it was last updated before `cutoff`.
+    /// The age is checked under the lock that removes the node, so a usage update that arrived in
+    /// the meantime keeps it. Returns whether the node was removed.
+    pub fn remove_node_if_stale(&self, ip: &Ipv4Addr, cutoff: std::time::SystemTime) -> bool {
+        let mut shard_lock = self.shard(ip).write().unwrap();
+
+        if shard_lock
+            .get(ip)
+            .is_none_or(|node| node.last_updated >= cutoff)
+        {
+            return false;
+        }
+        shard_lock.remove(ip);
+        drop(shard_lock);
+        self.publish_removed(ip);
+        true
+    }
+
+    fn publish_removed(&self, ip: &Ipv4Addr) {
+        self.publish(DataStoreEvent::NodeRemoved {
+            ip: *ip,
+            timestamp: now_timestamp(),
+        });
+    }
+
+    fn shard(&self, ip: &Ipv4Addr) -> &Shard {
+        &self.shards[u32::from(*ip) as usize % SHARD_COUNT]
+    }
+
+    /// Publish an event. Having no subscriber is not an error.
+    fn publish(&self, event: DataStoreEvent) {
+        let _ = self.events.send(event);
     }
 }
 impl Default for DataStore {
@@ -192,3 +714,197 @@ impl Default for DataStore {
         Self::new()
     }
 }
+
+/// Write the label file off the async workers when running on a runtime
+fn persist_labels(snapshot: Option<LabelSnapshot>) {
+    let Some(snapshot) = snapshot else {
+        return;
+    };
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            handle.spawn_blocking(move || snapshot.write());
+        }
+        Err(_) => snapshot.write(),
+    }
+}
+
+/// The Unix timestamp in seconds of a time
+fn unix_timestamp(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The current Unix timestamp in seconds
+fn now_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    const IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn usage(used_memory: u64) -> MachineUsage {
+        MachineUsage {
+            used_memory,
+            ..MachineUsage::default()
+        }
+    }
+
+    /// Run the threads, failing instead of hanging if they deadlock
+    fn run_within(timeout: Duration, threads: Vec<Box<dyn FnOnce() + Send>>) {
+        let (done, finished) = std::sync::mpsc::channel();
+        for thread in threads {
+            let done = done.clone();
+            std::thread::spawn(move || {
+                thread();
+                done.send(()).unwrap();
+            });
+        }
+        drop(done);
+        let deadline = std::time::Instant::now() + timeout;
+        while let Some(left) = deadline.checked_duration_since(std::time::Instant::now()) {
+            match finished.recv_timeout(left) {
+                Ok(()) => continue,
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+            }
+        }
+        panic!("the threads did not finish within {:?}", timeout);
+    }
+
+    #[test]
+    fn fresh_nodes_are_neither_removed_nor_marked_offline() {
+        let data_store = DataStore::new();
+        let before_update = SystemTime::now() - Duration::from_secs(1);
+        data_store.update_usage(IP, MachineUsage::default());
+
+        assert!(!data_store.mark_offline_if_stale(&IP, before_update));
+        assert!(!data_store.remove_node_if_stale(&IP, before_update));
+        assert_eq!(data_store.get_node(IP).unwrap().state, NodeState::Online);
+    }
+
+    #[test]
+    fn stale_nodes_are_marked_offline_then_removed() {
+        let data_store = DataStore::new();
+        data_store.update_usage(IP, MachineUsage::default());
+        let after_update = SystemTime::now() + Duration::from_secs(1);
+
+        assert!(data_store.mark_offline_if_stale(&IP, after_update));
+        assert!(!data_store.mark_offline_if_stale(&IP, after_update));
+        assert_eq!(data_store.get_node(IP).unwrap().state, NodeState::Offline);
+
+        assert!(data_store.remove_node_if_stale(&IP, after_update));
+        assert!(!data_store.contains_node(IP));
+        assert!(!data_store.remove_node_if_stale(&IP, after_update));
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        const WRITERS: u8 = 8;
+        const NODES_PER_WRITER: u8 = 8;
+        const UPDATES: u64 = 200;
+        let data_store = DataStore::init();
+        // each writer owns its nodes, so the last usage of a node is known
+        let nodes =
+            |writer: u8| (0..NODES_PER_WRITER).map(move |node| Ipv4Addr::new(10, 1, writer, node));
+        let removed = Ipv4Addr::new(10, 2, 0, 1);
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let mut threads: Vec<Box<dyn FnOnce() + Send>> = Vec::new();
+        for writer in 0..WRITERS {
+            let data_store = Arc::clone(&data_store);
+            threads.push(Box::new(move || {
+                for update in 1..=UPDATES {
+                    for ip in nodes(writer) {
+                        data_store.update_usage(ip, usage(update));
+                    }
+                }
+            }));
+        }
+        for _ in 0..4 {
+            let data_store = Arc::clone(&data_store);
+            let stop = Arc::clone(&stop);
+            threads.push(Box::new(move || {
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    for node in data_store.get_node_overview() {
+                        data_store.get_node(node.ip);
+                    }
+                    data_store.get_groups();
+                }
+            }));
+        }
+        {
+            // the labels are locked before a shard
+            let data_store = Arc::clone(&data_store);
+            let stop = Arc::clone(&stop);
+            threads.push(Box::new(move || {
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    for ip in nodes(0) {
+                        data_store.set_groups(ip, BTreeSet::from([String::from("web")]));
+                    }
+                }
+            }));
+        }
+        {
+            let data_store = Arc::clone(&data_store);
+            threads.push(Box::new(move || {
+                for update in 1..=UPDATES {
+                    data_store.update_usage(removed, usage(update));
+                    data_store.remove_node(&removed);
+                }
+            }));
+        }
+        {
+            let stop = Arc::clone(&stop);
+            threads.push(Box::new(move || {
+                std::thread::sleep(Duration::from_millis(200));
+                stop.store(true, std::sync::atomic::Ordering::Relaxed);
+            }));
+        }
+        run_within(Duration::from_secs(60), threads);
+
+        assert_eq!(
+            data_store.get_node_overview().len(),
+            usize::from(WRITERS) * usize::from(NODES_PER_WRITER)
+        );
+        for writer in 0..WRITERS {
+            for ip in nodes(writer) {
+                let node = data_store.get_node(ip).unwrap();
+                assert_eq!(node.usage.len(), UPDATES as usize);
+                // the newest usage first
+                assert!(
+                    node.usage
+                        .iter()
+                        .map(|usage| usage.machine_usage.used_memory)
+                        .eq((1..=UPDATES).rev()),
+                    "{}",
+                    ip
+                );
+            }
+        }
+        assert!(!data_store.contains_node(removed));
+    }
+
+    #[test]
+    fn usage_is_published_to_subscribers_only() {
+        let data_store = DataStore::new();
+        data_store.update_usage(IP, usage(1));
+
+        let mut events = data_store.subscribe();
+        data_store.update_usage(IP, usage(2));
+        match events.try_recv().unwrap() {
+            DataStoreEvent::UsageUpdated { ip, usage, .. } => {
+                assert_eq!((ip, usage.used_memory), (IP, 2));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Change events published by the data store.
+//!
+//! Every mutation of [`DataStore`](crate::store::data_store::DataStore) that matters to a consumer
+//! is published on a tokio broadcast channel, so the web server, the native app and alerting can
+//! subscribe without polling.
+
+use crate::schemas::device_info::{MachineInfo, MachineUsage};
+use crate::store::spec_history::FieldChange;
+use serde::Serialize;
+use std::net::Ipv4Addr;
+
+/// The number of events a lagging subscriber can fall behind before it starts losing them.
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum DataStoreEvent {
+    /// A node answered for the first time.
+    #[serde(rename_all = "camelCase")]
+    NodeDiscovered { ip: Ipv4Addr, timestamp: u64 },
+    /// The machine info of a node was received for the first time or has changed.
+    #[serde(rename_all = "camelCase")]
+    NodeSpecChanged {
+        ip: Ipv4Addr,
+        previous: Option<Box<MachineInfo>>,
+        current: Box<MachineInfo>,
+        /// The diff to the last recorded spec, empty when the spec has not changed since then
+        changes: Vec<FieldChange>,
+        timestamp: u64,
+    },
+    /// A node stopped answering, but it is still kept in the data store.
+    #[serde(rename_all = "camelCase")]
+    NodeOffline { ip: Ipv4Addr, timestamp: u64 },
+    /// A node was removed from the data store.
+    #[serde(rename_all = "camelCase")]
+    NodeRemoved { ip: Ipv4Addr, timestamp: u64 },
+    /// A new usage record was stored for a node.
+    #[serde(rename_all = "camelCase")]
+    UsageUpdated {
+        ip: Ipv4Addr,
+        usage: Box<MachineUsage>,
+        timestamp: u64,
+    },
+}
+
+impl DataStoreEvent {
+    /// The names of all events, in the order of the variants, as returned by [`Self::name`].
+    pub const NAMES: [&'static str; 5] = [
+        "nodeDiscovered",
+        "nodeSpecChanged",
+        "nodeOffline",
+        "nodeRemoved",
+        "usageUpdated",
+    ];
+
+    /// The IP address of the node this event is about.
+    pub fn ip(&self) -> Ipv4Addr {
+        match self {
+            DataStoreEvent::NodeDiscovered { ip, .. }
+            | DataStoreEvent::NodeSpecChanged { ip, .. }
+            | DataStoreEvent::NodeOffline { ip, .. }
+            | DataStoreEvent::NodeRemoved { ip, .. }
+            | DataStoreEvent::UsageUpdated { ip, .. } => *ip,
+        }
+    }
+
+    /// The name of the event as it is serialized in the `event` tag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataStoreEvent::NodeDiscovered { .. } => "nodeDiscovered",
+            DataStoreEvent::NodeSpecChanged { .. } => "nodeSpecChanged",
+            DataStoreEvent::NodeOffline { .. } => "nodeOffline",
+            DataStoreEvent::NodeRemoved { .. } => "nodeRemoved",
+            DataStoreEvent::UsageUpdated { .. } => "usageUpdated",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::machine_info;
+
+    #[test]
+    fn names_match_the_serialized_tags() {
+        let ip = Ipv4Addr::LOCALHOST;
+        let spec = machine_info("web-1", "Debian GNU/Linux", "x86_64");
+        let events = [
+            DataStoreEvent::NodeDiscovered { ip, timestamp: 0 },
+            DataStoreEvent::NodeSpecChanged {
+                ip,
+                previous: None,
+                current: Box::new(spec),
+                changes: Vec::new(),
+                timestamp: 0,
+            },
+            DataStoreEvent::NodeOffline { ip, timestamp: 0 },
+            DataStoreEvent::NodeRemoved { ip, timestamp: 0 },
+            DataStoreEvent::UsageUpdated {
+                ip,
+                usage: Box::default(),
+                timestamp: 0,
+            },
+        ];
+        assert_eq!(events.len(), DataStoreEvent::NAMES.len());
+        for (event, name) in events.iter().zip(DataStoreEvent::NAMES) {
+            assert_eq!(event.name(), name);
+            assert_eq!(serde_json::to_value(event).unwrap()["event"], name);
+        }
+    }
+}
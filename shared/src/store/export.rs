@@ -0,0 +1,473 @@
+//! Exports of the inventory and the usage history for spreadsheets and scripts.
+//!
+//! The CSV exports have one row per node or per usage record and write times as
+//! `YYYY-MM-DD HH:MM:SS` in UTC, which spreadsheets recognize as dates. Text cells that a
+//! spreadsheet would read as a formula are quoted, whichever program opens the file. The NDJSON
+//! exports have one JSON object per line and write times as Unix timestamps in seconds, like the
+//! API does.
+
+use crate::schemas::device_info::{MachineInfo, MachineUsage};
+use crate::store::labels::NodeLabels;
+use crate::store::node_query::average_cpu;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::net::Ipv4Addr;
+
+/// The byte order mark that makes Excel read a CSV file as UTF-8
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// CSV with a byte order mark
+    Excel,
+    /// JSON Lines
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv | ExportFormat::Excel => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv | ExportFormat::Excel => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Csv(csv::Error),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "failed to write the export: {}", e),
+            ExportError::Csv(e) => write!(f, "failed to write the CSV export: {}", e),
+            ExportError::Json(e) => write!(f, "failed to write the NDJSON export: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExportError::Io(e) => Some(e),
+            ExportError::Csv(e) => Some(e),
+            ExportError::Json(e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(error: std::io::Error) -> Self {
+        ExportError::Io(error)
+    }
+}
+
+impl From<csv::Error> for ExportError {
+    fn from(error: csv::Error) -> Self {
+        ExportError::Csv(error)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(error: serde_json::Error) -> Self {
+        ExportError::Json(error)
+    }
+}
+
+/// A row of the inventory export
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryRow {
+    pub ip: Ipv4Addr,
+    pub host_name: Option<String>,
+    pub os: Option<String>,
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub cpu_brand: Option<String>,
+    /// The number of physical cores
+    pub cores: Option<usize>,
+    /// Total RAM in bytes
+    pub total_memory: Option<u64>,
+    pub labels: BTreeMap<String, String>,
+    pub groups: BTreeSet<String>,
+    /// Unix timestamp in seconds of the first usage since the node was last found
+    pub first_seen: u64,
+    /// Unix timestamp in seconds
+    pub last_seen: u64,
+}
+
+impl InventoryRow {
+    pub(crate) fn new(
+        ip: Ipv4Addr,
+        info: Option<&MachineInfo>,
+        labels: NodeLabels,
+        first_seen: u64,
+        last_seen: u64,
+    ) -> Self {
+        Self {
+            ip,
+            host_name: info.map(|info| info.host_name.clone()),
+            os: info.map(|info| info.os.clone()),
+            os_version: info.map(|info| info.os_version.clone()),
+            kernel_version: info.map(|info| info.kernel_version.clone()),
+            cpu_brand: info.map(|info| info.brand.clone()),
+            cores: info.map(|info| info.number_of_cpu),
+            total_memory: info.map(|info| info.total_memory),
+            labels: labels.labels,
+            groups: labels.groups,
+            first_seen,
+            last_seen,
+        }
+    }
+}
+
+/// A row of the usage history export
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRow {
+    pub ip: Ipv4Addr,
+    pub host_name: Option<String>,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    /// The average over the logical CPUs in percent
+    pub cpu_usage: f64,
+    pub used_memory: u64,
+    pub total_memory: u64,
+    pub used_swap: u64,
+    pub total_swap: u64,
+    /// Bytes received per second
+    pub network_down: u64,
+    /// Bytes transmitted per second
+    pub network_up: u64,
+    pub load_average: f64,
+    pub process_count: usize,
+}
+
+impl UsageRow {
+    pub(crate) fn new(
+        ip: Ipv4Addr,
+        host_name: Option<String>,
+        timestamp: u64,
+        usage: &MachineUsage,
+    ) -> Self {
+        Self {
+            ip,
+            host_name,
+            timestamp,
+            cpu_usage: average_cpu(usage),
+            used_memory: usage.used_memory,
+            total_memory: usage.total_memory,
+            used_swap: usage.used_swap,
+            total_swap: usage.total_swap,
+            network_down: usage.network_down,
+            network_up: usage.network_up,
+            load_average: usage.load_average.one,
+            process_count: usage.process_count,
+        }
+    }
+}
+
+/// How a row is laid out in the CSV exports
+pub(crate) trait CsvRow {
+    const HEADER: &'static [&'static str];
+
+    fn record(&self) -> Vec<String>;
+}
+
+impl CsvRow for InventoryRow {
+    const HEADER: &'static [&'static str] = &[
+        "ip",
+        "host_name",
+        "os",
+        "os_version",
+        "kernel_version",
+        "cpu_brand",
+        "cores",
+        "total_memory",
+        "labels",
+        "groups",
+        "first_seen",
+        "last_seen",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        let labels = self
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(";");
+        let groups = self.groups.iter().cloned().collect::<Vec<_>>().join(";");
+        vec![
+            self.ip.to_string(),
+            text(self.host_name.as_deref()),
+            text(self.os.as_deref()),
+            text(self.os_version.as_deref()),
+            text(self.kernel_version.as_deref()),
+            text(self.cpu_brand.as_deref()),
+            self.cores
+                .map(|cores| cores.to_string())
+                .unwrap_or_default(),
+            self.total_memory
+                .map(|memory| memory.to_string())
+                .unwrap_or_default(),
+            text(Some(&labels)),
+            text(Some(&groups)),
+            format_utc(self.first_seen),
+            format_utc(self.last_seen),
+        ]
+    }
+}
+
+impl CsvRow for UsageRow {
+    const HEADER: &'static [&'static str] = &[
+        "ip",
+        "host_name",
+        "timestamp",
+        "cpu_usage",
+        "used_memory",
+        "total_memory",
+        "used_swap",
+        "total_swap",
+        "network_down",
+        "network_up",
+        "load_average",
+        "process_count",
+    ];
+
+    fn record(&self) -> Vec<String> {
+        vec![
+            self.ip.to_string(),
+            text(self.host_name.as_deref()),
+            format_utc(self.timestamp),
+            format!("{:.2}", self.cpu_usage),
+            self.used_memory.to_string(),
+            self.total_memory.to_string(),
+            self.used_swap.to_string(),
+            self.total_swap.to_string(),
+            self.network_down.to_string(),
+            self.network_up.to_string(),
+            format!("{:.2}", self.load_average),
+            self.process_count.to_string(),
+        ]
+    }
+}
+
+/// A text cell, the values reported by the nodes are not trusted to be opened in a spreadsheet
+fn text(value: Option<&str>) -> String {
+    let value = value.unwrap_or_default();
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Format a Unix timestamp as `YYYY-MM-DD HH:MM:SS` in UTC
+fn format_utc(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+    // the civil date of a day count since 1970-01-01, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60
+    )
+}
+
+/// Write the rows in the format
+pub(crate) fn write<R, W>(
+    rows: &[R],
+    format: ExportFormat,
+    mut writer: W,
+) -> Result<(), ExportError>
+where
+    R: CsvRow + Serialize,
+    W: Write,
+{
+    match format {
+        ExportFormat::Csv | ExportFormat::Excel => {
+            if format == ExportFormat::Excel {
+                writer.write_all(UTF8_BOM)?;
+            }
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            csv_writer.write_record(R::HEADER)?;
+            for row in rows {
+                csv_writer.write_record(row.record())?;
+            }
+            csv_writer.flush()?;
+        }
+        ExportFormat::Ndjson => {
+            let mut writer = std::io::BufWriter::new(writer);
+            for row in rows {
+                serde_json::to_writer(&mut writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::data_store::DataStore;
+    use crate::test_support::machine_info;
+
+    fn ip(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, last)
+    }
+
+    /// Two nodes with a usage record each, the host name of the first looks like a formula
+    fn data_store() -> DataStore {
+        let data_store = DataStore::new();
+        let usage = MachineUsage {
+            cpu_usage: vec![25.0, 75.0],
+            used_memory: 512,
+            total_memory: 1024,
+            ..MachineUsage::default()
+        };
+        data_store.update_usage(ip(2), usage.clone());
+        data_store.update_usage(ip(1), usage);
+        let machine_info = machine_info(
+            "=HYPERLINK(\"http://example.com\")",
+            "Debian GNU/Linux",
+            "x86_64",
+        );
+        data_store.update_node_information(ip(1), machine_info);
+        data_store
+    }
+
+    fn export_inventory(format: ExportFormat) -> String {
+        let mut output = Vec::new();
+        data_store().export_inventory(format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn export_usage(from: u64, to: u64, format: ExportFormat) -> String {
+        let mut output = Vec::new();
+        data_store()
+            .export_usage(from, to, format, &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn csv_quotes_formulas() {
+        let csv = export_inventory(ExportFormat::Csv);
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("ip,host_name,os,"));
+        assert!(lines[1].starts_with("10.0.0.1,\"'=HYPERLINK(\"\"http://example.com\"\")\","));
+        assert!(lines[2].starts_with("10.0.0.2,,,"));
+    }
+
+    #[test]
+    fn excel_has_a_byte_order_mark() {
+        let excel = export_inventory(ExportFormat::Excel);
+        let csv = export_inventory(ExportFormat::Csv);
+        assert_eq!(excel.as_bytes()[..3], *UTF8_BOM);
+        // the header follows the mark, and the cells are quoted like in the plain CSV
+        assert_eq!(excel[3..].lines().next(), csv.lines().next());
+        assert!(excel.contains("'=HYPERLINK"));
+        assert!(!csv.starts_with('\u{feff}'));
+    }
+
+    #[test]
+    fn text_cells_are_quoted() {
+        for formula in ["=1+1", "+1", "-1", "@SUM(A1)", "\tx", "\rx"] {
+            assert_eq!(text(Some(formula)), format!("'{}", formula));
+        }
+        assert_eq!(text(Some("web-1")), "web-1");
+        assert_eq!(text(None), "");
+    }
+
+    #[test]
+    fn times_are_utc() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_utc(1_700_000_000), "2023-11-14 22:13:20");
+    }
+
+    #[test]
+    fn usage_is_limited_to_the_window() {
+        let now = now();
+        let csv = export_usage(now - 60, now + 60, ExportFormat::Csv);
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("ip,host_name,timestamp,cpu_usage,"));
+        assert!(lines[1].starts_with("10.0.0.1,\"'=HYPERLINK"));
+        assert!(lines[1].contains(",50.00,512,1024,"));
+        assert!(lines[2].starts_with("10.0.0.2,,"));
+
+        let csv = export_usage(0, now - 3_600, ExportFormat::Csv);
+        assert_eq!(csv.lines().count(), 1);
+        let csv = export_usage(now + 3_600, now + 7_200, ExportFormat::Csv);
+        assert_eq!(csv.lines().count(), 1);
+    }
+
+    #[test]
+    fn ndjson_has_an_object_per_line() {
+        let now = now();
+        let ndjson = export_usage(now - 60, now + 60, ExportFormat::Ndjson);
+        let rows = ndjson
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["ip"], "10.0.0.1");
+        assert_eq!(rows[0]["hostName"], "=HYPERLINK(\"http://example.com\")");
+        assert!(
+            rows[0]["timestamp"]
+                .as_u64()
+                .is_some_and(|timestamp| timestamp >= now - 60)
+        );
+        assert_eq!(rows[1]["ip"], "10.0.0.2");
+        assert_eq!(rows[1]["cpuUsage"], 50.0);
+
+        assert_eq!(export_usage(0, now - 3_600, ExportFormat::Ndjson), "");
+    }
+}
@@ -0,0 +1,503 @@
+//! User-assigned labels and groups of nodes.
+//!
+//! Labels and groups are keyed by the machine ID a node reports, so they survive IP changes, and
+//! nodes that share a host name keep their own labels. A node that has not reported its machine
+//! info yet, or that has no machine ID, is labelled by its IP address. Those labels move to the
+//! machine ID once it is known. When a label file is configured, every change is written to it,
+//! so they survive restarts of the manager as well.
+//!
+//! Nodes can also declare labels in their own configuration. Those are merged with the assigned
+//! labels, and an assigned label wins when both have the same key.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{error, warn};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct NodeLabels {
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub groups: BTreeSet<String>,
+}
+
+impl NodeLabels {
+    /// Merge other labels into these, the labels of `other` win
+    fn merge(&mut self, other: NodeLabels) {
+        self.labels.extend(other.labels);
+        self.groups.extend(other.groups);
+    }
+}
+
+/// What the assigned labels of a node are stored under
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum LabelKey {
+    Machine(String),
+    Ip(Ipv4Addr),
+}
+
+impl LabelKey {
+    pub(crate) fn new(ip: Ipv4Addr, machine_id: Option<&str>) -> Self {
+        match machine_id {
+            Some(machine_id) => LabelKey::Machine(machine_id.to_string()),
+            None => LabelKey::Ip(ip),
+        }
+    }
+}
+
+impl std::fmt::Display for LabelKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelKey::Machine(machine_id) => write!(f, "machine/{}", machine_id),
+            LabelKey::Ip(ip) => write!(f, "ip/{}", ip),
+        }
+    }
+}
+
+impl std::str::FromStr for LabelKey {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some(("machine", machine_id)) if !machine_id.is_empty() => {
+                Ok(LabelKey::Machine(machine_id.to_string()))
+            }
+            Some(("ip", ip)) => ip.parse().map(LabelKey::Ip).map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The label file, shared by the snapshots that are written to it
+#[derive(Debug)]
+struct LabelFile {
+    path: PathBuf,
+    /// The version of the last snapshot written, locked while a snapshot is written
+    written: Mutex<u64>,
+}
+
+/// The labels at one point in time, to be written after the lock of the store is released
+#[derive(Debug)]
+pub(crate) struct LabelSnapshot {
+    file: Arc<LabelFile>,
+    version: u64,
+    entries: BTreeMap<String, NodeLabels>,
+}
+
+impl LabelSnapshot {
+    /// Write the labels to a temporary file first, so a crash never leaves a broken file behind.
+    /// A snapshot older than the one already written is dropped.
+    pub(crate) fn write(self) {
+        let mut written = self.file.written.lock().unwrap();
+        if *written >= self.version {
+            return;
+        }
+        let path = &self.file.path;
+        let content = match serde_json::to_string_pretty(&self.entries) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Failed to serialize the labels: {}", e);
+                return;
+            }
+        };
+        let temporary_path = path.with_extension("tmp");
+        match std::fs::write(&temporary_path, content)
+            .and_then(|_| std::fs::rename(&temporary_path, path))
+        {
+            Ok(()) => *written = self.version,
+            Err(e) => error!("Failed to write the label file {:?}: {}", path, e),
+        }
+    }
+}
+
+/// The assigned labels of every node, optionally backed by a JSON file.
+#[derive(Debug, Default)]
+pub(crate) struct LabelStore {
+    file: Option<Arc<LabelFile>>,
+    entries: HashMap<LabelKey, NodeLabels>,
+    /// Increased with every change, so an older snapshot never overwrites a newer one
+    version: u64,
+}
+
+impl LabelStore {
+    /// Load the labels from the given file.
+    /// The file is a JSON object from the key of a node, `machine/<machine ID>` or
+    /// `ip/<IPv4 address>`, to its labels and groups. A missing or broken file starts with no
+    /// labels, the file is rewritten with the next change.
+    pub(crate) fn load(path: PathBuf) -> Self {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str::<HashMap<String, NodeLabels>>(&content)
+                .unwrap_or_else(|e| {
+                    error!("Failed to parse the label file {:?}: {}", path, e);
+                    HashMap::new()
+                }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                error!("Failed to read the label file {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+        let entries = entries
+            .into_iter()
+            .filter_map(|(key, labels)| match key.parse::<LabelKey>() {
+                Ok(key) => Some((key, labels)),
+                // e.g. a key mistyped in a file edited by hand, the other nodes keep their labels
+                Err(()) => {
+                    warn!("Dropped the labels of the malformed key {:?}", key);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            file: Some(Arc::new(LabelFile {
+                path,
+                written: Mutex::new(0),
+            })),
+            entries,
+            version: 0,
+        }
+    }
+
+    pub(crate) fn get(&self, key: &LabelKey) -> NodeLabels {
+        self.entries.get(key).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn set_labels(
+        &mut self,
+        key: LabelKey,
+        labels: BTreeMap<String, String>,
+    ) -> NodeLabels {
+        self.update(key, |entry| entry.labels = labels)
+    }
+
+    pub(crate) fn set_groups(&mut self, key: LabelKey, groups: BTreeSet<String>) -> NodeLabels {
+        self.update(key, |entry| entry.groups = groups)
+    }
+
+    /// Update the entry of a key and drop it if nothing is left
+    fn update(&mut self, key: LabelKey, update: impl FnOnce(&mut NodeLabels)) -> NodeLabels {
+        let entry = self.entries.entry(key.clone()).or_default();
+        update(entry);
+        let entry = entry.clone();
+        if entry == NodeLabels::default() {
+            self.entries.remove(&key);
+        }
+        self.version += 1;
+        entry
+    }
+
+    /// Move the labels assigned to a node by its IP address to its machine ID.
+    /// The labels already stored under the machine ID win. Returns whether anything moved.
+    pub(crate) fn move_to_machine(&mut self, ip: Ipv4Addr, machine_id: &str) -> bool {
+        let Some(by_ip) = self.entries.remove(&LabelKey::Ip(ip)) else {
+            return false;
+        };
+        let entry = self
+            .entries
+            .entry(LabelKey::Machine(machine_id.to_string()))
+            .or_default();
+        let by_machine = std::mem::replace(entry, by_ip);
+        entry.merge(by_machine);
+        self.version += 1;
+        true
+    }
+
+    /// The labels to write to the label file, `None` without a label file
+    pub(crate) fn snapshot(&self) -> Option<LabelSnapshot> {
+        let file = self.file.as_ref()?;
+        Some(LabelSnapshot {
+            file: Arc::clone(file),
+            version: self.version,
+            entries: self
+                .entries
+                .iter()
+                .map(|(key, labels)| (key.to_string(), labels.clone()))
+                .collect(),
+        })
+    }
+}
+
+/// A single requirement of a label selector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Requirement {
+    Equals(String, String),
+    NotEquals(String, String),
+    In(String, BTreeSet<String>),
+    NotIn(String, BTreeSet<String>),
+    Exists(String),
+    NotExists(String),
+}
+
+impl Requirement {
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Requirement::Equals(key, value) => labels.get(key) == Some(value),
+            Requirement::NotEquals(key, value) => labels.get(key) != Some(value),
+            Requirement::In(key, values) => labels.get(key).is_some_and(|v| values.contains(v)),
+            Requirement::NotIn(key, values) => labels.get(key).is_none_or(|v| !values.contains(v)),
+            Requirement::Exists(key) => labels.contains_key(key),
+            Requirement::NotExists(key) => !labels.contains_key(key),
+        }
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            Requirement::Equals(key, _)
+            | Requirement::NotEquals(key, _)
+            | Requirement::In(key, _)
+            | Requirement::NotIn(key, _)
+            | Requirement::Exists(key)
+            | Requirement::NotExists(key) => key,
+        }
+    }
+
+    /// Parse `key in (a,b)` or `key notin (a,b)`, `None` if it is not a set requirement
+    fn parse_set(requirement: &str) -> Option<Result<Self, ()>> {
+        let (head, values) = requirement.strip_suffix(')')?.split_once('(')?;
+        let mut head = head.split_whitespace();
+        let (Some(key), Some(operator), None) = (head.next(), head.next(), head.next()) else {
+            return Some(Err(()));
+        };
+        let values = values
+            .split(',')
+            .map(|value| value.trim().to_string())
+            .collect::<BTreeSet<_>>();
+        if values.iter().any(String::is_empty) {
+            return Some(Err(()));
+        }
+        match operator {
+            "in" => Some(Ok(Requirement::In(key.to_string(), values))),
+            "notin" => Some(Ok(Requirement::NotIn(key.to_string(), values))),
+            _ => Some(Err(())),
+        }
+    }
+}
+
+/// A comma separated list of requirements that all have to match, e.g. `role=db,rack!=r3`.
+///
+/// Supported requirements are `key=value`, `key!=value`, `key in (a,b)`, `key notin (a,b)`,
+/// `key` (the label exists) and `!key` (the label does not exist). Like `!=`, `notin` also
+/// matches nodes without the label. An empty selector matches every node.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+impl LabelSelector {
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement.matches(labels))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelSelectorError(String);
+
+impl std::fmt::Display for LabelSelectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid label selector requirement: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for LabelSelectorError {}
+
+/// Split a selector at the commas outside of the value sets
+fn split_requirements(selector: &str) -> Result<Vec<&str>, LabelSelectorError> {
+    let mut requirements = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in selector.char_indices() {
+        match c {
+            '(' if depth == 0 => depth = 1,
+            ')' if depth == 1 => depth = 0,
+            '(' | ')' => return Err(LabelSelectorError(selector.to_string())),
+            ',' if depth == 0 => {
+                requirements.push(selector[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(LabelSelectorError(selector[start..].trim().to_string()));
+    }
+    requirements.push(selector[start..].trim());
+    Ok(requirements)
+}
+
+impl std::str::FromStr for LabelSelector {
+    type Err = LabelSelectorError;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        let mut requirements = Vec::new();
+        for requirement in split_requirements(selector)? {
+            if requirement.is_empty() {
+                continue;
+            }
+            let invalid = || LabelSelectorError(requirement.to_string());
+            let parsed = if let Some(set) = Requirement::parse_set(requirement) {
+                set.map_err(|_| invalid())?
+            } else if let Some((key, value)) = requirement.split_once("!=") {
+                Requirement::NotEquals(key.trim().to_string(), value.trim().to_string())
+            } else if let Some((key, value)) = requirement.split_once('=') {
+                Requirement::Equals(key.trim().to_string(), value.trim().to_string())
+            } else if let Some(key) = requirement.strip_prefix('!') {
+                Requirement::NotExists(key.trim().to_string())
+            } else {
+                Requirement::Exists(requirement.to_string())
+            };
+            if parsed.key().is_empty() || parsed.key().contains(char::is_whitespace) {
+                return Err(invalid());
+            }
+            requirements.push(parsed);
+        }
+        Ok(Self { requirements })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn matches(selector: &str, pairs: &[(&str, &str)]) -> bool {
+        selector
+            .parse::<LabelSelector>()
+            .unwrap()
+            .matches(&labels(pairs))
+    }
+
+    #[test]
+    fn equals_and_not_equals() {
+        assert!(matches("role=db", &[("role", "db")]));
+        assert!(!matches("role=db", &[("role", "web")]));
+        assert!(!matches("role=db", &[]));
+        assert!(matches("rack!=r3", &[("rack", "r1")]));
+        assert!(matches("rack!=r3", &[]));
+        assert!(!matches("rack!=r3", &[("rack", "r3")]));
+        assert!(matches(
+            " role = db , rack != r3 ",
+            &[("role", "db"), ("rack", "r1")]
+        ));
+    }
+
+    #[test]
+    fn in_and_not_in() {
+        assert!(matches("env in (prod, staging)", &[("env", "staging")]));
+        assert!(!matches("env in (prod,staging)", &[("env", "dev")]));
+        assert!(!matches("env in (prod)", &[]));
+        assert!(matches("env notin (prod,staging)", &[("env", "dev")]));
+        assert!(matches("env notin (prod)", &[]));
+        assert!(!matches("env notin (prod,staging)", &[("env", "prod")]));
+        assert!(matches(
+            "env in (prod,staging),role=db",
+            &[("env", "prod"), ("role", "db")]
+        ));
+    }
+
+    #[test]
+    fn exists_and_not_exists() {
+        assert!(matches("gpu", &[("gpu", "")]));
+        assert!(!matches("gpu", &[]));
+        assert!(matches("!gpu", &[]));
+        assert!(!matches("!gpu", &[("gpu", "a100")]));
+    }
+
+    #[test]
+    fn empty_selector_matches_everything() {
+        assert!(matches("", &[]));
+        assert!(matches(" , ", &[("role", "db")]));
+    }
+
+    #[test]
+    fn malformed_selectors() {
+        for selector in [
+            "=db",
+            "!=r3",
+            "!",
+            "env in (prod",
+            "env in prod)",
+            "env in ()",
+            "env in (prod,)",
+            "env within (prod)",
+            "in (prod)",
+            "env in ((prod))",
+            "two words",
+        ] {
+            assert!(
+                selector.parse::<LabelSelector>().is_err(),
+                "{selector:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn labels_move_from_the_ip_to_the_machine_id() {
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let mut store = LabelStore::default();
+        store.set_labels(LabelKey::Ip(ip), labels(&[("role", "db"), ("rack", "r1")]));
+        store.set_labels(
+            LabelKey::Machine(String::from("m1")),
+            labels(&[("rack", "r2")]),
+        );
+
+        assert!(store.move_to_machine(ip, "m1"));
+        assert_eq!(store.get(&LabelKey::Ip(ip)), NodeLabels::default());
+        assert_eq!(
+            store.get(&LabelKey::Machine(String::from("m1"))).labels,
+            labels(&[("role", "db"), ("rack", "r2")])
+        );
+        assert!(!store.move_to_machine(ip, "m1"));
+    }
+
+    #[test]
+    fn label_keys_round_trip() {
+        for key in [
+            LabelKey::Machine(String::from("4c4c4544")),
+            LabelKey::Ip(Ipv4Addr::new(192, 168, 1, 2)),
+        ] {
+            assert_eq!(key.to_string().parse::<LabelKey>(), Ok(key));
+        }
+        assert!("web-01".parse::<LabelKey>().is_err());
+    }
+
+    #[test]
+    fn malformed_keys_of_the_label_file_are_dropped() {
+        let directory = TempDir::new("labels-load");
+        let path = directory.write(
+            "labels.json",
+            r#"{
+                "machine/4c4c4544": {"labels": {"team": "infra"}},
+                "ip/10.0.0.5": {"groups": ["web"]},
+                "ip/10.0.0.256": {"labels": {"team": "lost"}},
+                "web-1": {"labels": {"team": "lost"}}
+            }"#,
+        );
+        let store = LabelStore::load(path);
+
+        assert_eq!(store.entries.len(), 2);
+        assert_eq!(
+            store
+                .get(&LabelKey::Machine(String::from("4c4c4544")))
+                .labels,
+            labels(&[("team", "infra")])
+        );
+        assert_eq!(
+            store.get(&LabelKey::Ip(Ipv4Addr::new(10, 0, 0, 5))).groups,
+            BTreeSet::from([String::from("web")])
+        );
+    }
+}
@@ -0,0 +1,498 @@
+//! Filtering, sorting and cursor pagination of the nodes.
+//!
+//! The cursor of a page is the sort value and the IP address of its last node, so the next page
+//! starts right after it even when nodes are added or removed in between. Ties of the sort value
+//! are broken by the IP address, which makes the order total.
+//!
+//! The sorts by a live value, the CPU, the memory and the last update, read the values anew for
+//! every page. A node whose value moves across the cursor between two pages is skipped or listed
+//! twice, so clients that need every node exactly once sort by the IP address or the host name.
+
+use crate::schemas::device_info::{MachineInfo, MachineUsage};
+use crate::store::data_store::NodeOverview;
+use crate::store::labels::{LabelSelector, NodeLabels};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::net::Ipv4Addr;
+
+/// The number of nodes of a page when no limit is given
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+/// The largest page a client may ask for
+pub const MAX_PAGE_LIMIT: usize = 500;
+
+/// The order of the nodes, e.g. `cpu_desc`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum NodeSort {
+    #[default]
+    IpAsc,
+    IpDesc,
+    HostNameAsc,
+    HostNameDesc,
+    /// The average usage over the logical CPUs
+    CpuAsc,
+    CpuDesc,
+    /// The used memory in percent of the total memory
+    MemoryAsc,
+    MemoryDesc,
+    LastUpdatedAsc,
+    LastUpdatedDesc,
+}
+
+/// The value a node is sorted by. Nodes without the value are always sorted last.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum SortValue {
+    Number(f64),
+    Text(String),
+}
+
+impl SortValue {
+    fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Number(a), Self::Number(b)) => a.total_cmp(b),
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::Number(_), Self::Text(_)) => Ordering::Less,
+            (Self::Text(_), Self::Number(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl NodeSort {
+    fn descending(self) -> bool {
+        matches!(
+            self,
+            Self::IpDesc
+                | Self::HostNameDesc
+                | Self::CpuDesc
+                | Self::MemoryDesc
+                | Self::LastUpdatedDesc
+        )
+    }
+
+    fn value(
+        self,
+        ip: Ipv4Addr,
+        machine_info: Option<&MachineInfo>,
+        usage: Option<&MachineUsage>,
+        last_updated: std::time::SystemTime,
+    ) -> Option<SortValue> {
+        match self {
+            Self::IpAsc | Self::IpDesc => Some(SortValue::Number(f64::from(u32::from(ip)))),
+            Self::HostNameAsc | Self::HostNameDesc => {
+                machine_info.map(|machine_info| SortValue::Text(machine_info.host_name.clone()))
+            }
+            Self::CpuAsc | Self::CpuDesc => usage
+                .filter(|usage| !usage.cpu_usage.is_empty())
+                .map(|usage| SortValue::Number(average_cpu(usage))),
+            Self::MemoryAsc | Self::MemoryDesc => {
+                usage.filter(|usage| usage.total_memory > 0).map(|usage| {
+                    SortValue::Number(usage.used_memory as f64 / usage.total_memory as f64 * 100.0)
+                })
+            }
+            Self::LastUpdatedAsc | Self::LastUpdatedDesc => Some(SortValue::Number(
+                last_updated
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+            )),
+        }
+    }
+}
+
+/// The average usage over the logical CPUs in percent, or over the effective CPUs when the node
+/// runs inside a container
+pub fn average_cpu(usage: &MachineUsage) -> f64 {
+    if let Some(effective_cpu_usage) = usage.effective_cpu_usage {
+        return f64::from(effective_cpu_usage);
+    }
+    if usage.cpu_usage.is_empty() {
+        return 0.0;
+    }
+    usage
+        .cpu_usage
+        .iter()
+        .map(|cpu| f64::from(*cpu))
+        .sum::<f64>()
+        / usage.cpu_usage.len() as f64
+}
+
+/// The position of a node in the order of a sort
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SortEntry {
+    value: Option<SortValue>,
+    ip: Ipv4Addr,
+}
+
+impl SortEntry {
+    pub(crate) fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+
+    fn compare(&self, other: &Self, sort: NodeSort) -> Ordering {
+        let by_value = match (&self.value, &other.value) {
+            (Some(a), Some(b)) if sort.descending() => b.compare(a),
+            (Some(a), Some(b)) => a.compare(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        by_value.then(self.ip.cmp(&other.ip))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CursorError {
+    Malformed,
+    /// The cursor was issued for another sort
+    SortMismatch,
+}
+
+impl std::fmt::Display for CursorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CursorError::Malformed => write!(f, "the cursor is malformed"),
+            CursorError::SortMismatch => write!(f, "the cursor was issued for another sort"),
+        }
+    }
+}
+
+impl std::error::Error for CursorError {}
+
+/// Where the next page starts, passed to the client as an opaque string
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeCursor {
+    sort: NodeSort,
+    #[serde(flatten)]
+    after: SortEntry,
+}
+
+impl std::fmt::Display for NodeCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| std::fmt::Error)?;
+        for byte in json.bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for NodeCursor {
+    type Err = CursorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.len().is_multiple_of(2) || !s.is_ascii() {
+            return Err(CursorError::Malformed);
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| CursorError::Malformed)?;
+        serde_json::from_slice(&bytes).map_err(|_| CursorError::Malformed)
+    }
+}
+
+/// The filters, the order and the page of a node listing
+#[derive(Debug, Clone, Default)]
+pub struct NodeQuery {
+    pub selector: LabelSelector,
+    pub group: Option<String>,
+    /// Matches a part of the OS name ignoring the case, e.g. `linux`
+    pub os: Option<String>,
+    /// Matches the architecture ignoring the case, e.g. `x86_64`
+    pub arch: Option<String>,
+    /// Searches the host name, the IP address and the description ignoring the case
+    pub search: Option<String>,
+    pub sort: NodeSort,
+    /// Capped at [`MAX_PAGE_LIMIT`], [`DEFAULT_PAGE_LIMIT`] if not given
+    pub limit: Option<usize>,
+    /// Continue after the cursor, which has to be of the same sort
+    pub cursor: Option<NodeCursor>,
+}
+
+impl NodeQuery {
+    pub(crate) fn validate(&self) -> Result<(), CursorError> {
+        match &self.cursor {
+            Some(cursor) if cursor.sort != self.sort => Err(CursorError::SortMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    pub(crate) fn matches(
+        &self,
+        ip: Ipv4Addr,
+        machine_info: Option<&MachineInfo>,
+        labels: &NodeLabels,
+        description: Option<&str>,
+    ) -> bool {
+        let equals = |filter: &Option<String>, value: Option<&str>| {
+            filter
+                .as_deref()
+                .is_none_or(|filter| value.is_some_and(|value| value.eq_ignore_ascii_case(filter)))
+        };
+        let search = self.search.as_deref().is_none_or(|search| {
+            let search = search.to_lowercase();
+            ip.to_string().contains(&search)
+                || machine_info.is_some_and(|info| info.host_name.to_lowercase().contains(&search))
+                || description.is_some_and(|d| d.to_lowercase().contains(&search))
+        });
+        // the OS name is usually the distribution, e.g. `Debian GNU/Linux`
+        let os = self.os.as_deref().is_none_or(|os| {
+            machine_info.is_some_and(|info| info.os.to_lowercase().contains(&os.to_lowercase()))
+        });
+        os && equals(&self.arch, machine_info.map(|info| info.arch.as_str()))
+            && search
+            && self.selector.matches(&labels.labels)
+            && self
+                .group
+                .as_deref()
+                .is_none_or(|group| labels.groups.contains(group))
+    }
+
+    pub(crate) fn entry(
+        &self,
+        ip: Ipv4Addr,
+        machine_info: Option<&MachineInfo>,
+        usage: Option<&MachineUsage>,
+        last_updated: std::time::SystemTime,
+    ) -> SortEntry {
+        SortEntry {
+            value: self.sort.value(ip, machine_info, usage, last_updated),
+            ip,
+        }
+    }
+
+    /// Keep the entries of the page in their order, returns whether more entries follow
+    pub(crate) fn page(&self, entries: &mut Vec<SortEntry>) -> bool {
+        if let Some(cursor) = &self.cursor {
+            entries.retain(|entry| entry.compare(&cursor.after, self.sort) == Ordering::Greater);
+        }
+        let limit = self.limit();
+        let more = entries.len() > limit;
+        if more {
+            // only the page itself needs to be sorted
+            entries.select_nth_unstable_by(limit, |a, b| a.compare(b, self.sort));
+            entries.truncate(limit);
+        }
+        entries.sort_unstable_by(|a, b| a.compare(b, self.sort));
+        more
+    }
+
+    pub(crate) fn cursor_after(&self, entry: SortEntry) -> NodeCursor {
+        NodeCursor {
+            sort: self.sort,
+            after: entry,
+        }
+    }
+}
+
+/// A page of nodes
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodePage {
+    pub nodes: Vec<NodeOverview>,
+    /// The number of nodes matching the filters over every page
+    pub total: usize,
+    /// `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::machine_info;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::time::{Duration, SystemTime};
+
+    fn usage(cpu: f32) -> MachineUsage {
+        MachineUsage {
+            cpu_usage: vec![cpu],
+            ..MachineUsage::default()
+        }
+    }
+
+    fn ip(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, last)
+    }
+
+    /// The IP addresses of every page, following the cursors
+    fn pages(query: &mut NodeQuery, entries: &[SortEntry]) -> Vec<Vec<Ipv4Addr>> {
+        let mut pages = Vec::new();
+        loop {
+            let mut page = entries.to_vec();
+            let more = query.page(&mut page);
+            pages.push(page.iter().map(SortEntry::ip).collect());
+            if !more {
+                return pages;
+            }
+            let cursor = query.cursor_after(page.last().unwrap().clone());
+            query.cursor = Some(cursor.to_string().parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let query = NodeQuery {
+            sort: NodeSort::HostNameDesc,
+            ..NodeQuery::default()
+        };
+        let info = machine_info("web-1", "Ubuntu", "x86_64");
+        let cursor = query.cursor_after(query.entry(ip(1), Some(&info), None, SystemTime::now()));
+        assert_eq!(cursor.to_string().parse::<NodeCursor>(), Ok(cursor.clone()));
+
+        let cursor = query.cursor_after(query.entry(ip(2), None, None, SystemTime::now()));
+        assert_eq!(cursor.to_string().parse::<NodeCursor>(), Ok(cursor));
+
+        assert_eq!("abc".parse::<NodeCursor>(), Err(CursorError::Malformed));
+        assert_eq!("zz".parse::<NodeCursor>(), Err(CursorError::Malformed));
+        assert_eq!("7b7d".parse::<NodeCursor>(), Err(CursorError::Malformed));
+    }
+
+    #[test]
+    fn cursor_of_another_sort_is_rejected() {
+        let mut query = NodeQuery::default();
+        query.cursor = Some(query.cursor_after(query.entry(ip(1), None, None, SystemTime::now())));
+        assert_eq!(query.validate(), Ok(()));
+        query.sort = NodeSort::CpuDesc;
+        assert_eq!(query.validate(), Err(CursorError::SortMismatch));
+    }
+
+    #[test]
+    fn filters_are_combined() {
+        let debian = machine_info("db-1", "Debian GNU/Linux", "x86_64");
+        let arm = machine_info("edge-1", "Ubuntu", "aarch64");
+        let labels = NodeLabels {
+            labels: BTreeMap::from([(String::from("env"), String::from("prod"))]),
+            groups: BTreeSet::from([String::from("databases")]),
+        };
+        let query = |query: NodeQuery| {
+            (
+                query.matches(ip(1), Some(&debian), &labels, Some("Primary database")),
+                query.matches(ip(2), Some(&arm), &NodeLabels::default(), None),
+                query.matches(ip(3), None, &labels, None),
+            )
+        };
+
+        assert_eq!(query(NodeQuery::default()), (true, true, true));
+        // the OS matches a part of the name ignoring the case
+        let os = |os: &str| NodeQuery {
+            os: Some(os.to_string()),
+            ..NodeQuery::default()
+        };
+        assert_eq!(query(os("linux")), (true, false, false));
+        assert_eq!(query(os("UBUNTU")), (false, true, false));
+        // the architecture matches as a whole ignoring the case
+        let arch = |arch: &str| NodeQuery {
+            arch: Some(arch.to_string()),
+            ..NodeQuery::default()
+        };
+        assert_eq!(query(arch("X86_64")), (true, false, false));
+        assert_eq!(query(arch("x86")), (false, false, false));
+        // the search looks at the IP address, the host name and the description
+        let search = |search: &str| NodeQuery {
+            search: Some(search.to_string()),
+            ..NodeQuery::default()
+        };
+        assert_eq!(query(search("10.0.0.3")), (false, false, true));
+        assert_eq!(query(search("EDGE")), (false, true, false));
+        assert_eq!(query(search("database")), (true, false, false));
+
+        let combined = NodeQuery {
+            selector: "env=prod".parse().unwrap(),
+            group: Some(String::from("databases")),
+            os: Some(String::from("debian")),
+            arch: Some(String::from("x86_64")),
+            search: Some(String::from("db")),
+            ..NodeQuery::default()
+        };
+        assert_eq!(query(combined.clone()), (true, false, false));
+        let other_group = NodeQuery {
+            group: Some(String::from("web")),
+            ..combined
+        };
+        assert_eq!(query(other_group), (false, false, false));
+    }
+
+    #[test]
+    fn missing_values_are_sorted_last_and_ties_by_ip() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let nodes = [
+            (ip(4), None),
+            (ip(3), Some(usage(20.0))),
+            (ip(1), Some(usage(50.0))),
+            (ip(2), Some(usage(20.0))),
+            (ip(5), None),
+        ];
+        let sorted = |sort: NodeSort| {
+            let mut query = NodeQuery {
+                sort,
+                ..NodeQuery::default()
+            };
+            let entries = nodes
+                .iter()
+                .map(|(ip, usage)| query.entry(*ip, None, usage.as_ref(), now))
+                .collect::<Vec<SortEntry>>();
+            pages(&mut query, &entries).concat()
+        };
+
+        assert_eq!(
+            sorted(NodeSort::CpuAsc),
+            vec![ip(2), ip(3), ip(1), ip(4), ip(5)]
+        );
+        assert_eq!(
+            sorted(NodeSort::CpuDesc),
+            vec![ip(1), ip(2), ip(3), ip(4), ip(5)]
+        );
+        assert_eq!(
+            sorted(NodeSort::IpDesc),
+            vec![ip(5), ip(4), ip(3), ip(2), ip(1)]
+        );
+        // every node has no host name, so only the IP address orders them
+        assert_eq!(
+            sorted(NodeSort::HostNameDesc),
+            vec![ip(1), ip(2), ip(3), ip(4), ip(5)]
+        );
+    }
+
+    #[test]
+    fn pages_follow_the_cursor() {
+        let now = SystemTime::now();
+        let mut query = NodeQuery {
+            sort: NodeSort::CpuDesc,
+            limit: Some(2),
+            ..NodeQuery::default()
+        };
+        let entries = (1..=5)
+            .map(|last| query.entry(ip(last), None, Some(&usage(f32::from(last % 3))), now))
+            .collect::<Vec<SortEntry>>();
+        assert_eq!(
+            pages(&mut query, &entries),
+            vec![vec![ip(2), ip(5)], vec![ip(1), ip(4)], vec![ip(3)]]
+        );
+    }
+
+    #[test]
+    fn average_cpu_of_containers_is_the_effective_usage() {
+        let host = MachineUsage {
+            cpu_usage: vec![10.0, 30.0],
+            ..MachineUsage::default()
+        };
+        assert_eq!(average_cpu(&host), 20.0);
+
+        let container = MachineUsage {
+            effective_cpus: Some(0.5),
+            effective_cpu_usage: Some(90.0),
+            ..host
+        };
+        assert_eq!(average_cpu(&container), 90.0);
+        assert_eq!(average_cpu(&MachineUsage::default()), 0.0);
+    }
+}
@@ -0,0 +1,250 @@
+//! The history of the machine info of nodes.
+//!
+//! Every time a node reports a machine info that differs from the last one it reported, a new
+//! versioned entry is recorded together with the field-level diff, so OS upgrades and hardware
+//! changes can be audited. The boot time and the IP addresses are not compared, a reboot or a new
+//! DHCP lease alone records no version.
+
+use crate::schemas::device_info::MachineInfo;
+use serde::Serialize;
+
+/// The maximum number of entries kept per node. The oldest entries are dropped first.
+const MAX_ENTRIES: usize = 100;
+
+/// A change of a single field of the machine info.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChange {
+    /// The field name as it is serialized in `MachineInfo`
+    pub field: String,
+    pub previous: serde_json::Value,
+    pub current: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct SpecHistoryEntry {
+    /// Starts with 1 and is incremented with every change
+    pub version: u32,
+    // Unix timestamp in seconds
+    pub timestamp: u64,
+    pub machine_info: MachineInfo,
+    /// The diff to the previous version. Empty for the first version.
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SpecHistory {
+    entries: std::collections::VecDeque<SpecHistoryEntry>,
+}
+
+impl SpecHistory {
+    /// Record the machine info if it differs from the latest entry in more than the volatile fields.
+    /// Returns the changes if a new entry was recorded.
+    pub(crate) fn record(
+        &mut self,
+        machine_info: &MachineInfo,
+        timestamp: u64,
+    ) -> Option<Vec<FieldChange>> {
+        let (version, changes) = match self.entries.back() {
+            Some(latest)
+                if latest.machine_info.without_volatile_fields()
+                    == machine_info.without_volatile_fields() =>
+            {
+                return None;
+            }
+            Some(latest) => (
+                latest.version + 1,
+                diff_machine_info(&latest.machine_info, machine_info),
+            ),
+            None => (1, Vec::new()),
+        };
+
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(SpecHistoryEntry {
+            version,
+            timestamp,
+            machine_info: machine_info.clone(),
+            changes: changes.clone(),
+        });
+        Some(changes)
+    }
+
+    pub(crate) fn latest(&self) -> Option<&SpecHistoryEntry> {
+        self.entries.back()
+    }
+
+    /// The entries from the oldest to the newest
+    pub(crate) fn entries(&self) -> Vec<SpecHistoryEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+/// Compare two machine infos field by field.
+/// The fields are compared by their serialized form, so new fields of `MachineInfo` are covered
+/// without changing this function. A field that differs only in the volatile parts is not a
+/// change, a changed field is reported with its full values.
+pub fn diff_machine_info(previous: &MachineInfo, current: &MachineInfo) -> Vec<FieldChange> {
+    let (Some(previous), Some(current), Some(stable_previous), Some(stable_current)) = (
+        to_object(previous),
+        to_object(current),
+        to_object(&previous.without_volatile_fields()),
+        to_object(&current.without_volatile_fields()),
+    ) else {
+        return Vec::new();
+    };
+
+    let mut changes = Vec::new();
+    for (field, current_value) in current.iter() {
+        if stable_previous.get(field) == stable_current.get(field) {
+            continue;
+        }
+        changes.push(FieldChange {
+            field: field.clone(),
+            previous: previous
+                .get(field)
+                .cloned()
+                .unwrap_or(serde_json::Value::Null),
+            current: current_value.clone(),
+        });
+    }
+    changes
+}
+
+fn to_object(machine_info: &MachineInfo) -> Option<serde_json::Map<String, serde_json::Value>> {
+    match serde_json::to_value(machine_info) {
+        Ok(serde_json::Value::Object(object)) => Some(object),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::device_info::NetworkInterfaceInfo;
+    use crate::test_support::machine_info;
+
+    fn spec() -> MachineInfo {
+        MachineInfo {
+            boot_time: 1_700_000_000,
+            network_interfaces: vec![NetworkInterfaceInfo {
+                name: String::from("eth0"),
+                mac_address: String::from("52:54:00:12:34:56"),
+                ip_addresses: vec![String::from("10.0.0.5")],
+                speed: Some(1000),
+            }],
+            ..machine_info("web-1", "Debian GNU/Linux", "x86_64")
+        }
+    }
+
+    #[test]
+    fn versions_are_recorded_on_change_only() {
+        let mut history = SpecHistory::default();
+        let mut machine_info = spec();
+        assert_eq!(history.record(&machine_info, 1), Some(Vec::new()));
+        assert_eq!(history.record(&machine_info, 2), None);
+
+        machine_info.os_version = String::from("2.0");
+        let changes = history.record(&machine_info, 3).unwrap();
+        assert_eq!(
+            changes,
+            [FieldChange {
+                field: String::from("osVersion"),
+                previous: serde_json::json!("1.0"),
+                current: serde_json::json!("2.0"),
+            }]
+        );
+
+        let entries = history.entries();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.version, entry.timestamp))
+                .collect::<Vec<_>>(),
+            [(1, 1), (2, 3)]
+        );
+        assert_eq!(entries[1].changes, changes);
+        assert_eq!(history.latest().unwrap().machine_info, machine_info);
+    }
+
+    #[test]
+    fn volatile_fields_record_no_version() {
+        let mut history = SpecHistory::default();
+        history.record(&spec(), 1);
+
+        let mut rebooted = spec();
+        rebooted.boot_time += 3600;
+        rebooted.network_interfaces[0].ip_addresses = vec![String::from("10.0.0.9")];
+        assert_eq!(history.record(&rebooted, 2), None);
+        assert!(diff_machine_info(&spec(), &rebooted).is_empty());
+        assert_eq!(history.entries().len(), 1);
+    }
+
+    #[test]
+    fn changed_fields_carry_their_volatile_parts() {
+        let mut replaced = spec();
+        replaced.boot_time += 3600;
+        replaced.network_interfaces[0].mac_address = String::from("52:54:00:65:43:21");
+        replaced.network_interfaces[0].ip_addresses = vec![String::from("10.0.0.9")];
+
+        let changes = diff_machine_info(&spec(), &replaced);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "networkInterfaces");
+        assert_eq!(changes[0].previous[0]["ipAddresses"][0], "10.0.0.5");
+        assert_eq!(changes[0].current[0]["ipAddresses"][0], "10.0.0.9");
+    }
+
+    #[test]
+    fn fields_are_diffed_independently() {
+        let mut upgraded = spec();
+        upgraded.kernel_version = String::from("6.12.0");
+        upgraded.total_memory *= 2;
+        upgraded.machine_id = Some(String::from("4c4c4544"));
+
+        let fields = diff_machine_info(&spec(), &upgraded)
+            .into_iter()
+            .map(|change| (change.field, change.previous, change.current))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [
+                (
+                    String::from("kernelVersion"),
+                    serde_json::json!("6.1.0"),
+                    serde_json::json!("6.12.0")
+                ),
+                (
+                    String::from("machineId"),
+                    serde_json::Value::Null,
+                    serde_json::json!("4c4c4544")
+                ),
+                (
+                    String::from("totalMemory"),
+                    serde_json::json!(spec().total_memory),
+                    serde_json::json!(upgraded.total_memory)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn oldest_versions_are_dropped_past_the_limit() {
+        let mut history = SpecHistory::default();
+        let mut machine_info = spec();
+        for version in 1..=MAX_ENTRIES as u64 + 5 {
+            machine_info.os_version = version.to_string();
+            history.record(&machine_info, version);
+        }
+
+        let entries = history.entries();
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries.first().unwrap().version, 6);
+        assert_eq!(entries.last().unwrap().version, MAX_ENTRIES as u32 + 5);
+        // the diff of the oldest kept entry is the one to its dropped predecessor
+        assert_eq!(entries[0].changes[0].previous, serde_json::json!("5"));
+    }
+}
@@ -0,0 +1,341 @@
+//! Totals and breakdowns over the whole fleet.
+//!
+//! The CPU usage of a node is the average over its logical CPUs in its latest usage record, the
+//! CPU statistics of the fleet are taken over the online nodes that have reported a usage.
+
+use crate::schemas::device_info::MachineUsage;
+use crate::store::data_store::NodeState;
+use crate::store::node_query::average_cpu;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+/// The number of nodes listed as the busiest
+const BUSIEST_COUNT: usize = 10;
+/// The key of the nodes that do not report a value, e.g. without machine info yet
+const UNKNOWN: &str = "unknown";
+
+/// What the summary needs of a node
+pub(crate) struct SummaryNode<'a> {
+    pub(crate) ip: Ipv4Addr,
+    pub(crate) host_name: Option<String>,
+    pub(crate) os: Option<String>,
+    pub(crate) arch: Option<String>,
+    pub(crate) state: NodeState,
+    pub(crate) usage: Option<&'a MachineUsage>,
+    /// The value of the label the summary is grouped by
+    pub(crate) group: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct StateCount {
+    pub online: usize,
+    pub offline: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryTotals {
+    /// Bytes, summed over the latest usage of every node
+    pub total_memory: u64,
+    pub used_memory: u64,
+}
+
+/// CPU usage in percent, `None` when no online node has reported a usage
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CpuStatistics {
+    pub average: Option<f64>,
+    pub p95: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct BusyNode {
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = Ipv4))]
+    pub ip: Ipv4Addr,
+    pub host_name: Option<String>,
+    /// Percent, averaged over the logical CPUs
+    pub cpu_usage: f64,
+    /// Percent of the total memory
+    pub memory_usage: f64,
+}
+
+/// A node whose machine info was recorded recently. Version 1 is a newly discovered node.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedNode {
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = Ipv4))]
+    pub ip: Ipv4Addr,
+    pub host_name: String,
+    pub version: u32,
+    // Unix timestamp in seconds
+    pub timestamp: u64,
+    /// The names of the fields that changed, empty for a newly discovered node
+    pub changed_fields: Vec<String>,
+}
+
+/// The totals of the nodes sharing a label value
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct GroupSummary {
+    pub node_count: usize,
+    pub by_state: StateCount,
+    pub memory: MemoryTotals,
+    pub cpu: CpuStatistics,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FleetSummary {
+    pub node_count: usize,
+    pub by_os: BTreeMap<String, usize>,
+    pub by_arch: BTreeMap<String, usize>,
+    pub by_state: StateCount,
+    pub memory: MemoryTotals,
+    pub cpu: CpuStatistics,
+    /// The online nodes with the highest CPU usage, the busiest first
+    pub busiest: Vec<BusyNode>,
+    /// The nodes whose machine info was recorded in the requested window, the newest first
+    pub recently_changed: Vec<ChangedNode>,
+    /// Keyed by the value of the label, only present when grouped by a label.
+    /// Nodes without the label are counted under `unknown`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by_label: Option<BTreeMap<String, GroupSummary>>,
+}
+
+/// The totals shared by the fleet and the label groups
+#[derive(Default)]
+struct Totals {
+    node_count: usize,
+    by_state: StateCount,
+    memory: MemoryTotals,
+    cpu: Vec<f64>,
+}
+
+impl Totals {
+    fn add(&mut self, node: &SummaryNode) {
+        self.node_count += 1;
+        match node.state {
+            NodeState::Online => self.by_state.online += 1,
+            NodeState::Offline => self.by_state.offline += 1,
+        }
+        if let Some(usage) = node.usage {
+            self.memory.total_memory += usage.total_memory;
+            self.memory.used_memory += usage.used_memory;
+            if node.state == NodeState::Online && !usage.cpu_usage.is_empty() {
+                self.cpu.push(average_cpu(usage));
+            }
+        }
+    }
+
+    fn cpu_statistics(&mut self) -> CpuStatistics {
+        if self.cpu.is_empty() {
+            return CpuStatistics::default();
+        }
+        self.cpu.sort_unstable_by(f64::total_cmp);
+        // nearest rank
+        let p95 = (self.cpu.len() * 95).div_ceil(100).max(1) - 1;
+        CpuStatistics {
+            average: Some(self.cpu.iter().sum::<f64>() / self.cpu.len() as f64),
+            p95: Some(self.cpu[p95]),
+            max: self.cpu.last().copied(),
+        }
+    }
+
+    fn into_group(mut self) -> GroupSummary {
+        GroupSummary {
+            node_count: self.node_count,
+            by_state: self.by_state,
+            cpu: self.cpu_statistics(),
+            memory: self.memory,
+        }
+    }
+}
+
+pub(crate) fn summarize(
+    nodes: &[SummaryNode],
+    mut recently_changed: Vec<ChangedNode>,
+    grouped: bool,
+) -> FleetSummary {
+    let mut totals = Totals::default();
+    let mut by_os = BTreeMap::new();
+    let mut by_arch = BTreeMap::new();
+    let mut by_label: BTreeMap<String, Totals> = BTreeMap::new();
+    for node in nodes.iter() {
+        totals.add(node);
+        *by_os
+            .entry(node.os.clone().unwrap_or_else(|| UNKNOWN.to_string()))
+            .or_default() += 1;
+        *by_arch
+            .entry(node.arch.clone().unwrap_or_else(|| UNKNOWN.to_string()))
+            .or_default() += 1;
+        if grouped {
+            by_label
+                .entry(node.group.clone().unwrap_or_else(|| UNKNOWN.to_string()))
+                .or_default()
+                .add(node);
+        }
+    }
+
+    let mut busiest = nodes
+        .iter()
+        .filter(|node| node.state == NodeState::Online)
+        .filter_map(|node| {
+            let usage = node.usage.filter(|usage| !usage.cpu_usage.is_empty())?;
+            Some(BusyNode {
+                ip: node.ip,
+                host_name: node.host_name.clone(),
+                cpu_usage: average_cpu(usage),
+                memory_usage: if usage.total_memory > 0 {
+                    usage.used_memory as f64 / usage.total_memory as f64 * 100.0
+                } else {
+                    0.0
+                },
+            })
+        })
+        .collect::<Vec<BusyNode>>();
+    busiest.sort_unstable_by(|a, b| b.cpu_usage.total_cmp(&a.cpu_usage).then(a.ip.cmp(&b.ip)));
+    busiest.truncate(BUSIEST_COUNT);
+
+    recently_changed.sort_unstable_by(|a, b| b.timestamp.cmp(&a.timestamp).then(a.ip.cmp(&b.ip)));
+
+    FleetSummary {
+        node_count: totals.node_count,
+        by_os,
+        by_arch,
+        by_state: totals.by_state,
+        memory: totals.memory,
+        cpu: totals.cpu_statistics(),
+        busiest,
+        recently_changed,
+        by_label: grouped.then(|| {
+            by_label
+                .into_iter()
+                .map(|(value, totals)| (value, totals.into_group()))
+                .collect()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(cpu: f32, used_memory: u64) -> MachineUsage {
+        MachineUsage {
+            cpu_usage: vec![cpu],
+            used_memory,
+            total_memory: 100,
+            ..MachineUsage::default()
+        }
+    }
+
+    fn node<'a>(last: u8, state: NodeState, usage: Option<&'a MachineUsage>) -> SummaryNode<'a> {
+        SummaryNode {
+            ip: Ipv4Addr::new(10, 0, 0, last),
+            host_name: None,
+            os: None,
+            arch: None,
+            state,
+            usage,
+            group: None,
+        }
+    }
+
+    #[test]
+    fn p95_is_the_nearest_rank() {
+        // 1 to 20 percent, the 95th percentile is the 19th value
+        let usages = (1..=20)
+            .map(|cpu| usage(cpu as f32, 0))
+            .collect::<Vec<MachineUsage>>();
+        let nodes = usages
+            .iter()
+            .enumerate()
+            .map(|(i, usage)| node(i as u8, NodeState::Online, Some(usage)))
+            .collect::<Vec<SummaryNode>>();
+        let cpu = summarize(&nodes, Vec::new(), false).cpu;
+        assert_eq!(cpu.p95, Some(19.0));
+        assert_eq!(cpu.max, Some(20.0));
+        assert_eq!(cpu.average, Some(10.5));
+
+        // with fewer than 20 values the 95th percentile is the maximum
+        let cpu = summarize(&nodes[..3], Vec::new(), false).cpu;
+        assert_eq!(cpu.p95, Some(3.0));
+        let cpu = summarize(&nodes[..1], Vec::new(), false).cpu;
+        assert_eq!(cpu.p95, Some(1.0));
+    }
+
+    #[test]
+    fn empty_fleet() {
+        let summary = summarize(&[], Vec::new(), true);
+        assert_eq!(summary.node_count, 0);
+        assert_eq!(summary.cpu.average, None);
+        assert_eq!(summary.cpu.p95, None);
+        assert_eq!(summary.cpu.max, None);
+        assert!(summary.busiest.is_empty());
+        assert!(summary.by_label.is_some_and(|by_label| by_label.is_empty()));
+    }
+
+    #[test]
+    fn offline_nodes_count_but_have_no_cpu() {
+        let usage = usage(80.0, 30);
+        let nodes = [
+            node(1, NodeState::Offline, Some(&usage)),
+            node(2, NodeState::Offline, None),
+        ];
+        let summary = summarize(&nodes, Vec::new(), false);
+        assert_eq!(summary.node_count, 2);
+        assert_eq!(summary.by_state.offline, 2);
+        assert_eq!(summary.by_state.online, 0);
+        // the memory of the latest usage is kept, the CPU only counts for online nodes
+        assert_eq!(summary.memory.used_memory, 30);
+        assert_eq!(summary.cpu.average, None);
+        assert!(summary.busiest.is_empty());
+        assert_eq!(summary.by_os.get(UNKNOWN), Some(&2));
+        assert!(summary.by_label.is_none());
+    }
+
+    #[test]
+    fn nodes_without_the_label_are_grouped_as_unknown() {
+        let busy = usage(90.0, 50);
+        let idle = usage(10.0, 20);
+        let mut prod = node(1, NodeState::Online, Some(&busy));
+        prod.group = Some(String::from("prod"));
+        prod.os = Some(String::from("Ubuntu"));
+        let unlabelled = node(2, NodeState::Online, Some(&idle));
+        let offline = node(3, NodeState::Offline, None);
+        let summary = summarize(&[prod, unlabelled, offline], Vec::new(), true);
+
+        let by_label = summary.by_label.unwrap();
+        assert_eq!(by_label.keys().collect::<Vec<_>>(), vec!["prod", UNKNOWN]);
+        let prod = &by_label["prod"];
+        assert_eq!(prod.node_count, 1);
+        assert_eq!(prod.cpu.max, Some(90.0));
+        let unknown = &by_label[UNKNOWN];
+        assert_eq!(unknown.node_count, 2);
+        assert_eq!(unknown.by_state.offline, 1);
+        assert_eq!(unknown.cpu.average, Some(10.0));
+        assert_eq!(unknown.memory.used_memory, 20);
+
+        assert_eq!(summary.by_os.get("Ubuntu"), Some(&1));
+        assert_eq!(summary.by_os.get(UNKNOWN), Some(&2));
+        assert_eq!(
+            summary
+                .busiest
+                .iter()
+                .map(|node| node.ip)
+                .collect::<Vec<_>>(),
+            vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! Fixtures shared by the unit tests.
+
+use crate::schemas::device_info::MachineInfo;
+
+/// A machine info of a made-up host, so the tests do not depend on the host running them
+pub(crate) fn machine_info(host_name: &str, os: &str, arch: &str) -> MachineInfo {
+    MachineInfo {
+        os: os.to_string(),
+        os_version: String::from("1.0"),
+        host_name: host_name.to_string(),
+        kernel_version: String::from("6.1.0"),
+        number_of_cpu: 4,
+        number_of_logical_cpu: 8,
+        arch: arch.to_string(),
+        brand: String::from("Test CPU"),
+        total_memory: 16 * 1024 * 1024 * 1024,
+        ..MachineInfo::default()
+    }
+}
+
+/// A directory removed with everything in it when dropped
+pub(crate) struct TempDir(std::path::PathBuf);
+
+impl TempDir {
+    /// `name` keeps the directories of concurrent tests apart
+    pub(crate) fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("network-discovery-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+
+    /// Write a file, creating the directories on the way
+    pub(crate) fn write(&self, relative: &str, content: &str) -> std::path::PathBuf {
+        let path = self.0.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
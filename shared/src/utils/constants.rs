@@ -1,2 +1,4 @@
 pub const TARGET_PORT: u16 = 49152;
 pub const HOST_PORT: u16 = 49153;
+/// The largest payload of a UDP datagram over IPv4, the size of the receive buffers
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
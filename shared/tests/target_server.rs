@@ -0,0 +1,125 @@
+//! The allowlist and the rate limit of the `TargetServer`, exercised over local sockets.
+
+use shared::config::node_config::{AccessConfig, NodeConfig, RateLimitConfig};
+use shared::schemas::manager_messages::ManagerRequest;
+use shared::schemas::target_messages::ResponseSchema;
+use shared::server::request_guard::DroppedRequests;
+use shared::server::target_server::TargetServer;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Long enough for a reply over the loopback, short enough to keep the tests fast
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn config(access: AccessConfig) -> NodeConfig {
+    NodeConfig {
+        access,
+        ..NodeConfig::default()
+    }
+}
+
+/// A rate limit that does not refill within a test
+fn rate_limit(burst: u32) -> RateLimitConfig {
+    RateLimitConfig {
+        requests_per_second: 0.001,
+        burst,
+    }
+}
+
+/// Serve on an ephemeral port of the loopback
+async fn start(config: NodeConfig) -> (Arc<TargetServer>, SocketAddr) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let server = Arc::new(TargetServer::new(config));
+    let serving = Arc::clone(&server);
+    tokio::spawn(async move { serving.serve(socket, Ipv4Addr::LOCALHOST).await });
+    (server, address)
+}
+
+async fn client(ip: &str) -> UdpSocket {
+    UdpSocket::bind(format!("{}:0", ip)).await.unwrap()
+}
+
+fn spec_request() -> String {
+    ManagerRequest::new(String::from("127.0.0.1")).spec_request_json()
+}
+
+/// Send the requests, then count the replies until none arrives for a while
+async fn replies(socket: &UdpSocket, server: SocketAddr, requests: &[String]) -> usize {
+    for request in requests {
+        socket.send_to(request.as_bytes(), server).await.unwrap();
+    }
+    let mut buf = vec![0; 65_507];
+    let mut replies = 0;
+    while let Ok(Ok(amount)) = tokio::time::timeout(REPLY_TIMEOUT, socket.recv(&mut buf)).await {
+        serde_json::from_slice::<ResponseSchema>(&buf[..amount]).unwrap();
+        replies += 1;
+    }
+    replies
+}
+
+#[tokio::test]
+async fn answers_requests_within_the_rate_limit() {
+    let (server, address) = start(config(AccessConfig::default())).await;
+    let socket = client("127.0.0.1").await;
+
+    assert_eq!(replies(&socket, address, &[spec_request()]).await, 1);
+    assert_eq!(server.dropped_requests(), DroppedRequests::default());
+}
+
+#[tokio::test]
+async fn drops_requests_above_the_burst() {
+    let (server, address) = start(config(AccessConfig {
+        rate_limit: rate_limit(3),
+        ..AccessConfig::default()
+    }))
+    .await;
+    let socket = client("127.0.0.1").await;
+
+    let requests = vec![spec_request(); 10];
+    assert_eq!(replies(&socket, address, &requests).await, 3);
+    assert_eq!(server.dropped_requests().rate_limited, 7);
+}
+
+#[tokio::test]
+async fn limits_each_source_separately() {
+    let (server, address) = start(config(AccessConfig {
+        rate_limit: rate_limit(2),
+        ..AccessConfig::default()
+    }))
+    .await;
+    let first = client("127.0.0.1").await;
+    let second = client("127.0.0.2").await;
+
+    let requests = vec![spec_request(); 5];
+    assert_eq!(replies(&first, address, &requests).await, 2);
+    assert_eq!(replies(&second, address, &requests).await, 2);
+    assert_eq!(server.dropped_requests().rate_limited, 6);
+}
+
+#[tokio::test]
+async fn ignores_sources_outside_the_allowlist() {
+    let (server, address) = start(config(AccessConfig {
+        allow: vec!["10.0.0.0/8".parse().unwrap(), "127.0.0.2".parse().unwrap()],
+        ..AccessConfig::default()
+    }))
+    .await;
+    let denied = client("127.0.0.1").await;
+    let allowed = client("127.0.0.2").await;
+
+    assert_eq!(replies(&denied, address, &[spec_request()]).await, 0);
+    assert_eq!(replies(&allowed, address, &[spec_request()]).await, 1);
+    assert_eq!(server.dropped_requests().not_allowed, 1);
+}
+
+#[tokio::test]
+async fn counts_malformed_requests() {
+    let (server, address) = start(config(AccessConfig::default())).await;
+    let socket = client("127.0.0.1").await;
+
+    let requests = [String::from("not json"), spec_request()];
+    assert_eq!(replies(&socket, address, &requests).await, 1);
+    assert_eq!(server.dropped_requests().malformed, 1);
+}
@@ -0,0 +1,45 @@
+//! The browser dashboard, embedded in the binary so the server needs no files next to it.
+//!
+//! The dashboard is plain HTML, CSS and JavaScript without a build step. It only uses the JSON
+//! endpoints, so it shows what any other client of the API sees.
+
+use axum::http::header;
+use axum::response::IntoResponse;
+
+const INDEX_HTML: &str = include_str!("../static/index.html");
+const DASHBOARD_JS: &str = include_str!("../static/dashboard.js");
+const DASHBOARD_CSS: &str = include_str!("../static/dashboard.css");
+
+/// The assets change with the binary only, but a browser should not keep an old version around
+/// after an upgrade
+const CACHE_CONTROL: &str = "no-cache";
+
+pub(crate) async fn index() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::CACHE_CONTROL, CACHE_CONTROL),
+        ],
+        INDEX_HTML,
+    )
+}
+
+pub(crate) async fn script() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "text/javascript; charset=utf-8"),
+            (header::CACHE_CONTROL, CACHE_CONTROL),
+        ],
+        DASHBOARD_JS,
+    )
+}
+
+pub(crate) async fn stylesheet() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "text/css; charset=utf-8"),
+            (header::CACHE_CONTROL, CACHE_CONTROL),
+        ],
+        DASHBOARD_CSS,
+    )
+}
@@ -0,0 +1,98 @@
+//! The JSON error body every endpoint of the REST API answers with, e.g.
+//! `{"code": "nodeNotFound", "message": "no node with the IP address 10.0.0.5"}`.
+
+use axum::Json;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use shared::server::manager_handle::ManagerError;
+use std::net::Ipv4Addr;
+
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ErrorBody {
+    /// A stable identifier of the error, e.g. `invalidIp` or `nodeNotFound`
+    code: String,
+    /// A human readable description
+    message: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    body: ErrorBody,
+}
+
+impl ApiError {
+    pub(crate) fn new(status: StatusCode, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: ErrorBody {
+                code: code.to_string(),
+                message: message.into(),
+            },
+        }
+    }
+
+    pub(crate) fn bad_request(code: &str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub(crate) fn node_not_found(ip: Ipv4Addr) -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "nodeNotFound",
+            format!("no node with the IP address {}", ip),
+        )
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+impl From<ManagerError> for ApiError {
+    fn from(error: ManagerError) -> Self {
+        let (status, code) = match error {
+            ManagerError::Timeout => (StatusCode::GATEWAY_TIMEOUT, "nodeTimeout"),
+            ManagerError::Closed => (StatusCode::SERVICE_UNAVAILABLE, "managerUnavailable"),
+        };
+        Self::new(status, code, error.to_string())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(rejection.status(), "invalidQuery", rejection.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), "invalidBody", rejection.body_text())
+    }
+}
+
+/// `Query` with the rejection as an [`ApiError`]
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub(crate) struct ApiQuery<T>(pub(crate) T);
+
+/// `Json` with the rejection as an [`ApiError`]
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub(crate) struct ApiJson<T>(pub(crate) T);
+
+/// Parse the IP address of a path
+pub(crate) fn parse_ip(ip: &str) -> Result<Ipv4Addr, ApiError> {
+    ip.parse::<Ipv4Addr>()
+        .map_err(|_| ApiError::bad_request("invalidIp", format!("{:?} is not an IPv4 address", ip)))
+}
+
+/// The answer to an unknown route
+pub(crate) async fn not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "notFound", "no such endpoint")
+}
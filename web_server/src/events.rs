@@ -0,0 +1,319 @@
+//! Live feed of the data store events over Server-Sent Events and WebSocket.
+//!
+//! Both endpoints take the same filters as query parameters, e.g.
+//! `/events?nodes=10.0.0.5,10.0.0.6&events=nodeDiscovered,nodeRemoved`. Without a filter, or with
+//! an empty list, every event is streamed. A client that falls behind is told how many events it missed.
+
+use crate::AppState;
+use crate::error::{ApiError, ApiQuery, ErrorBody};
+use axum::extract::State;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::IntoResponse;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use shared::store::events::DataStoreEvent;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tracing::debug;
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct EventQuery {
+    /// comma separated IP addresses of the nodes
+    nodes: Option<String>,
+    /// comma separated event names such as `nodeDiscovered` or `usageUpdated`
+    events: Option<String>,
+}
+
+/// The events a client is interested in, `None` matches everything
+#[derive(Debug, Clone, Default)]
+struct EventFilter {
+    nodes: Option<HashSet<Ipv4Addr>>,
+    events: Option<HashSet<&'static str>>,
+}
+
+impl EventFilter {
+    fn parse(query: &EventQuery) -> Result<Self, ApiError> {
+        // an empty list does not filter
+        let split = |list: Option<&str>| {
+            let items = list?
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<String>>();
+            (!items.is_empty()).then_some(items)
+        };
+        let nodes = match split(query.nodes.as_deref()) {
+            Some(nodes) => Some(
+                nodes
+                    .iter()
+                    .map(|ip| {
+                        ip.parse::<Ipv4Addr>().map_err(|_| {
+                            ApiError::bad_request(
+                                "invalidFilter",
+                                format!("{:?} is not an IPv4 address", ip),
+                            )
+                        })
+                    })
+                    .collect::<Result<HashSet<Ipv4Addr>, ApiError>>()?,
+            ),
+            None => None,
+        };
+        let events = match split(query.events.as_deref()) {
+            Some(events) => Some(
+                events
+                    .iter()
+                    .map(|event| {
+                        DataStoreEvent::NAMES
+                            .iter()
+                            .find(|name| **name == event)
+                            .copied()
+                            .ok_or_else(|| {
+                                ApiError::bad_request(
+                                    "invalidFilter",
+                                    format!("{:?} is not an event name", event),
+                                )
+                            })
+                    })
+                    .collect::<Result<HashSet<&'static str>, ApiError>>()?,
+            ),
+            None => None,
+        };
+        Ok(Self { nodes, events })
+    }
+
+    fn matches(&self, event: &DataStoreEvent) -> bool {
+        self.nodes
+            .as_ref()
+            .is_none_or(|nodes| nodes.contains(&event.ip()))
+            && self
+                .events
+                .as_ref()
+                .is_none_or(|events| events.contains(event.name()))
+    }
+}
+
+/// The message sent instead of the events a lagging client missed
+fn lagged_message(skipped: u64) -> String {
+    serde_json::json!({ "event": "lagged", "skipped": skipped }).to_string()
+}
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    params(EventQuery),
+    responses(
+        (status = 200, description = "Server-Sent Events named after the data store events", content_type = "text/event-stream"),
+        (status = 400, description = "The filter is invalid", body = ErrorBody),
+    )
+)]
+pub(crate) async fn sse(
+    ApiQuery(query): ApiQuery<EventQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let filter = EventFilter::parse(&query)?;
+    let stream = BroadcastStream::new(state.data_store.subscribe()).filter_map(move |event| {
+        let event = match event {
+            Ok(event) if filter.matches(&event) => Event::default()
+                .event(event.name())
+                .json_data(&event)
+                .ok()?,
+            Ok(_) => return None,
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => Event::default()
+                .event("lagged")
+                .data(lagged_message(skipped)),
+        };
+        Some(Ok::<Event, Infallible>(event))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws",
+    params(EventQuery),
+    responses(
+        (status = 101, description = "A WebSocket sending the data store events as JSON text messages"),
+        (status = 400, description = "The filter is invalid", body = ErrorBody),
+    )
+)]
+pub(crate) async fn websocket(
+    upgrade: WebSocketUpgrade,
+    ApiQuery(query): ApiQuery<EventQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let filter = EventFilter::parse(&query)?;
+    Ok(upgrade.on_upgrade(move |socket| stream_to_websocket(socket, state, filter)))
+}
+
+/// Send the events until the client closes the socket or the data store shuts down
+async fn stream_to_websocket(mut socket: WebSocket, state: Arc<AppState>, filter: EventFilter) {
+    let mut events = state.data_store.subscribe();
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) if filter.matches(&event) => match serde_json::to_string(&event) {
+                    Ok(json) => json,
+                    Err(_) => continue,
+                },
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => lagged_message(skipped),
+                Err(RecvError::Closed) => break,
+            },
+            received = socket.recv() => match received {
+                // the feed is one-way, anything but a close is ignored
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if socket.send(Message::Text(message.into())).await.is_err() {
+            break;
+        }
+    }
+    debug!("WebSocket client disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use shared::schemas::device_info::MachineInfo;
+
+    fn query(nodes: Option<&str>, events: Option<&str>) -> EventQuery {
+        EventQuery {
+            nodes: nodes.map(str::to_string),
+            events: events.map(str::to_string),
+        }
+    }
+
+    fn parse_error(query: EventQuery) -> StatusCode {
+        EventFilter::parse(&query)
+            .unwrap_err()
+            .into_response()
+            .status()
+    }
+
+    /// One event of every variant about the given node
+    fn every_event(ip: Ipv4Addr) -> Vec<DataStoreEvent> {
+        vec![
+            DataStoreEvent::NodeDiscovered { ip, timestamp: 0 },
+            DataStoreEvent::NodeSpecChanged {
+                ip,
+                previous: None,
+                current: Box::new(MachineInfo::default()),
+                changes: Vec::new(),
+                timestamp: 0,
+            },
+            DataStoreEvent::NodeOffline { ip, timestamp: 0 },
+            DataStoreEvent::NodeRemoved { ip, timestamp: 0 },
+            DataStoreEvent::UsageUpdated {
+                ip,
+                usage: Box::default(),
+                timestamp: 0,
+            },
+        ]
+    }
+
+    #[test]
+    fn lists_are_split_and_trimmed() {
+        let filter = EventFilter::parse(&query(
+            Some(" 10.0.0.5, 10.0.0.6 ,"),
+            Some("nodeDiscovered , usageUpdated"),
+        ))
+        .unwrap();
+        assert_eq!(
+            filter.nodes.unwrap(),
+            HashSet::from([Ipv4Addr::new(10, 0, 0, 5), Ipv4Addr::new(10, 0, 0, 6)])
+        );
+        assert_eq!(
+            filter.events.unwrap(),
+            HashSet::from(["nodeDiscovered", "usageUpdated"])
+        );
+    }
+
+    #[test]
+    fn empty_lists_do_not_filter() {
+        for list in ["", " ", ",", " , "] {
+            let filter = EventFilter::parse(&query(Some(list), Some(list))).unwrap();
+            assert!(
+                filter.nodes.is_none() && filter.events.is_none(),
+                "{:?}",
+                list
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        assert_eq!(
+            parse_error(query(Some("10.0.0.5,web-1"), None)),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            parse_error(query(Some("10.0.0.256"), None)),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            parse_error(query(None, Some("nodeDiscovered,nodeRebooted"))),
+            StatusCode::BAD_REQUEST
+        );
+        // the names are case sensitive, like the serialized tags
+        assert_eq!(
+            parse_error(query(None, Some("NodeDiscovered"))),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn no_filter_matches_every_event() {
+        let filter = EventFilter::default();
+        for event in every_event(Ipv4Addr::new(10, 0, 0, 5)) {
+            assert!(filter.matches(&event), "{}", event.name());
+        }
+    }
+
+    #[test]
+    fn nodes_filter_matches_every_event_of_the_nodes() {
+        let filter = EventFilter::parse(&query(Some("10.0.0.5"), None)).unwrap();
+        for event in every_event(Ipv4Addr::new(10, 0, 0, 5)) {
+            assert!(filter.matches(&event), "{}", event.name());
+        }
+        for event in every_event(Ipv4Addr::new(10, 0, 0, 6)) {
+            assert!(!filter.matches(&event), "{}", event.name());
+        }
+    }
+
+    #[test]
+    fn events_filter_matches_each_variant_by_name() {
+        let ip = Ipv4Addr::new(10, 0, 0, 5);
+        for name in DataStoreEvent::NAMES {
+            let filter = EventFilter::parse(&query(None, Some(name))).unwrap();
+            for event in every_event(ip) {
+                assert_eq!(filter.matches(&event), event.name() == name, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn both_filters_must_match() {
+        let filter =
+            EventFilter::parse(&query(Some("10.0.0.5"), Some("nodeRemoved,nodeOffline"))).unwrap();
+        let matching = every_event(Ipv4Addr::new(10, 0, 0, 5))
+            .iter()
+            .filter(|event| filter.matches(event))
+            .map(DataStoreEvent::name)
+            .collect::<HashSet<_>>();
+        assert_eq!(matching, HashSet::from(["nodeOffline", "nodeRemoved"]));
+        assert!(
+            !every_event(Ipv4Addr::new(10, 0, 0, 6))
+                .iter()
+                .any(|event| filter.matches(event))
+        );
+    }
+}
@@ -1,13 +1,38 @@
+mod auth;
+mod dashboard;
+mod error;
+mod events;
+mod metrics;
+mod openapi;
+mod tls;
+
+use crate::auth::Authenticator;
+use crate::error::{ApiError, ApiJson, ApiQuery, ErrorBody, parse_ip};
 use axum::extract::{Path, State};
-use axum::http::StatusCode;
-use axum::{routing, Json};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, middleware, routing};
+use shared::config::manager_config::ManagerConfig;
+use shared::config::web_server_config::{Role, WebServerConfig};
+use shared::schemas::device_info::ProcessInfo;
+use shared::schemas::manager_messages::ProcessSortKey;
+use shared::server::manager_handle::ManagerHandle;
 use shared::store::data_store::{DataStore, DataStoreType};
+use shared::store::export::{ExportError, ExportFormat};
+use shared::store::labels::{LabelSelector, NodeLabels};
+use shared::store::node_query::{NodeCursor, NodeQuery, NodeSort};
+use shared::store::spec_history::SpecHistoryEntry;
+use shared::store::summary::FleetSummary;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
-use tracing::Level;
+use tracing::{Level, warn};
+use utoipa::OpenApi;
 
 struct AppState {
     // Add shared state here if needed
     data_store: DataStoreType,
+    manager: ManagerHandle,
+    auth: Arc<Authenticator>,
 }
 
 #[tokio::main]
@@ -19,127 +44,763 @@ async fn main() {
         .with_thread_names(true)
         .init();
 
-    let data_store = DataStore::init();
+    let config = ManagerConfig::load();
+    let data_store = DataStore::init_with_config(&config);
 
     // run manager server
     let data_store_for_server = data_store.clone();
     let manager_server = shared::server::manager_server::ManagerServer::new(data_store_for_server);
-    let manager_server_handler = tokio::spawn(async move {
+    let manager = manager_server.handle();
+    tokio::spawn(async move {
         manager_server.run().await;
     });
 
+    let web_config = WebServerConfig::load();
+    let client_auth = web_config
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.client_auth.as_ref());
+    let auth = Authenticator::new(&web_config.auth, client_auth).unwrap_or_else(|e| panic!("{e}"));
+    if !auth.is_enabled() {
+        warn!(
+            "No authentication is configured, every client has the {:?} role",
+            auth.anonymous_role().unwrap_or(Role::Viewer)
+        );
+    }
+
     let shared_state = Arc::new(AppState {
         data_store: Arc::clone(&data_store),
+        manager,
+        auth: Arc::new(auth),
     });
 
-    let app = axum::Router::new()
-        .route("/", routing::get(|| async { "Hello, World!" }))
+    let app = router(shared_state);
+
+    match web_config.tls {
+        Some(tls_config) => tls::serve(app, web_config.listen, tls_config).await,
+        None => {
+            let listener = tokio::net::TcpListener::bind(web_config.listen)
+                .await
+                .unwrap();
+            axum::serve(listener, app).await.unwrap();
+        }
+    }
+}
+
+/// The routes, each group behind the role it requires
+fn router(shared_state: Arc<AppState>) -> axum::Router {
+    // the dashboard assets and the API description contain no data
+    let public = axum::Router::new()
+        .route("/", routing::get(dashboard::index))
+        .route("/dashboard.js", routing::get(dashboard::script))
+        .route("/dashboard.css", routing::get(dashboard::stylesheet))
+        .route("/openapi.json", routing::get(openapi));
+    let read = axum::Router::new()
         .route("/nodes", routing::get(node_overview))
         .route("/nodes/{ip}", routing::get(get_node))
-        .with_state(shared_state);
+        .route("/nodes/{ip}/spec-history", routing::get(get_spec_history))
+        .route("/groups", routing::get(get_groups))
+        .route("/events", routing::get(events::sse))
+        .route("/ws", routing::get(events::websocket))
+        .route("/metrics", routing::get(metrics::metrics))
+        .nest("/api/v1", api_v1());
+    let operate = axum::Router::new()
+        .route("/nodes/{ip}/labels", routing::put(set_labels))
+        .route("/nodes/{ip}/groups", routing::put(set_groups))
+        .route("/nodes/{ip}/processes", routing::get(get_processes))
+        .route("/nodes/{ip}/refresh", routing::post(refresh_node))
+        .route("/discovery/scan", routing::post(scan))
+        .route_layer(middleware::from_fn(auth::require_operator));
+    let admin = axum::Router::new()
+        .route("/nodes/{ip}", routing::delete(forget_node))
+        .route_layer(middleware::from_fn(auth::require_admin));
+    let api = read
+        .merge(operate)
+        .merge(admin)
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&shared_state),
+            auth::authenticate,
+        ));
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    public
+        .merge(api)
+        .fallback(error::not_found)
+        .with_state(shared_state)
 }
 
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct NodeFilter {
+    /// label selector such as `role=db,rack!=r3`
+    selector: Option<String>,
+    group: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/nodes",
+    params(NodeFilter),
+    responses(
+        (status = 200, description = "The latest usage of every node", body = Vec<return_type::NodesData>),
+        (status = 400, description = "The selector is invalid", body = ErrorBody),
+    )
+)]
 async fn node_overview(
+    ApiQuery(filter): ApiQuery<NodeFilter>,
     State(state): State<Arc<AppState>>,
-) -> Json<Vec<crate::return_type::NodesData>> {
-    let store = state.data_store.read().await;
-    let nodes = store.get_node_overview();
-    drop(store);
-    Json(
+) -> Result<Json<Vec<crate::return_type::NodesData>>, ApiError> {
+    let selector = filter
+        .selector
+        .as_deref()
+        .unwrap_or_default()
+        .parse::<LabelSelector>()
+        .map_err(|e| ApiError::bad_request("invalidSelector", e.to_string()))?;
+    let nodes = state
+        .data_store
+        .filter_node_overview(&selector, filter.group.as_deref());
+    Ok(Json(
         nodes
-            .iter()
-            .map(|node| {
-                crate::return_type::NodesData::new(
-                    node.ip,
-                    node.machine_info.clone(),
-                    node.usage.clone(),
-                    node.last_updated
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                )
-            })
+            .into_iter()
+            .map(crate::return_type::NodesData::from)
             .collect::<Vec<crate::return_type::NodesData>>(),
-    )
+    ))
 }
 
+#[utoipa::path(
+    get,
+    path = "/nodes/{ip}",
+    params(("ip" = String, Path, description = "IPv4 address of the node")),
+    responses(
+        (status = 200, description = "The node with its usage history", body = return_type::Node),
+        (status = 400, description = "The IP address is invalid", body = ErrorBody),
+        (status = 404, description = "The node is unknown", body = ErrorBody),
+    )
+)]
 async fn get_node(
     Path(ip): Path<String>,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Option<crate::return_type::Node>>, StatusCode> {
-    let store = state.data_store.read().await;
-    let ip = match ip.parse::<std::net::Ipv4Addr>() {
-        Ok(ip) => ip,
-        Err(_) => {
-            return Err(StatusCode::BAD_REQUEST);
-        }
-    };
-    let node = store.get_node(ip);
-    drop(store);
-    Ok(Json(node.map(|node| {
-        return_type::Node::new(
-            node.ip,
-            node.machine_info,
-            node.usage,
-            node.last_updated
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+) -> Result<Json<crate::return_type::Node>, ApiError> {
+    let ip = parse_ip(&ip)?;
+    match state.data_store.get_node(ip) {
+        Some(node) => Ok(Json(return_type::Node::from(node))),
+        None => Err(ApiError::node_not_found(ip)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/nodes/{ip}/spec-history",
+    params(("ip" = String, Path, description = "IPv4 address of the node")),
+    responses(
+        (status = 200, description = "Every recorded version of the machine info", body = Vec<SpecHistoryEntry>),
+        (status = 400, description = "The IP address is invalid", body = ErrorBody),
+        (status = 404, description = "No machine info was ever recorded for the node", body = ErrorBody),
+    )
+)]
+async fn get_spec_history(
+    Path(ip): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<SpecHistoryEntry>>, ApiError> {
+    let ip = parse_ip(&ip)?;
+    match state.data_store.get_spec_history(ip) {
+        Some(history) => Ok(Json(history)),
+        None => Err(ApiError::node_not_found(ip)),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/nodes/{ip}/labels",
+    params(("ip" = String, Path, description = "IPv4 address of the node")),
+    request_body = BTreeMap<String, String>,
+    responses(
+        (status = 200, description = "The labels and groups of the node", body = NodeLabels),
+        (status = 400, description = "The IP address or the body is invalid", body = ErrorBody),
+        (status = 404, description = "The node is unknown", body = ErrorBody),
+    )
+)]
+async fn set_labels(
+    Path(ip): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ApiJson(labels): ApiJson<BTreeMap<String, String>>,
+) -> Result<Json<NodeLabels>, ApiError> {
+    let ip = parse_ip(&ip)?;
+    match state.data_store.set_labels(ip, labels) {
+        Some(labels) => Ok(Json(labels)),
+        None => Err(ApiError::node_not_found(ip)),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/nodes/{ip}/groups",
+    params(("ip" = String, Path, description = "IPv4 address of the node")),
+    request_body = BTreeSet<String>,
+    responses(
+        (status = 200, description = "The labels and groups of the node", body = NodeLabels),
+        (status = 400, description = "The IP address or the body is invalid", body = ErrorBody),
+        (status = 404, description = "The node is unknown", body = ErrorBody),
+    )
+)]
+async fn set_groups(
+    Path(ip): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ApiJson(groups): ApiJson<BTreeSet<String>>,
+) -> Result<Json<NodeLabels>, ApiError> {
+    let ip = parse_ip(&ip)?;
+    match state.data_store.set_groups(ip, groups) {
+        Some(labels) => Ok(Json(labels)),
+        None => Err(ApiError::node_not_found(ip)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/groups",
+    responses(
+        (status = 200, description = "The IP addresses of the known members of each group", body = BTreeMap<String, BTreeSet<String>>),
+    )
+)]
+async fn get_groups(
+    State(state): State<Arc<AppState>>,
+) -> Json<BTreeMap<String, BTreeSet<std::net::Ipv4Addr>>> {
+    Json(state.data_store.get_groups())
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query, rename_all = "camelCase")]
+struct ProcessQuery {
+    /// The number of processes, 10 by default
+    limit: Option<usize>,
+    sort_by: Option<ProcessSortKey>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/nodes/{ip}/processes",
+    params(("ip" = String, Path, description = "IPv4 address of the node"), ProcessQuery),
+    responses(
+        (status = 200, description = "The top processes of the node", body = Vec<ProcessInfo>),
+        (status = 400, description = "The IP address or the query is invalid", body = ErrorBody),
+        (status = 404, description = "The node is unknown", body = ErrorBody),
+        (status = 503, description = "The manager is not running", body = ErrorBody),
+        (status = 504, description = "The node did not answer in time", body = ErrorBody),
+    )
+)]
+async fn get_processes(
+    Path(ip): Path<String>,
+    ApiQuery(query): ApiQuery<ProcessQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<ProcessInfo>>, ApiError> {
+    let ip = parse_ip(&ip)?;
+    if !state.data_store.contains_node(ip) {
+        return Err(ApiError::node_not_found(ip));
+    }
+    let processes = state
+        .manager
+        .processes(
+            ip,
+            query.limit.unwrap_or(10),
+            query.sort_by.unwrap_or_default(),
         )
-    })))
+        .await?;
+    Ok(Json(processes))
+}
+
+#[utoipa::path(
+    post,
+    path = "/nodes/{ip}/refresh",
+    params(("ip" = String, Path, description = "IPv4 address of the node")),
+    responses(
+        (status = 202, description = "The node was asked for its machine info again"),
+        (status = 400, description = "The IP address is invalid", body = ErrorBody),
+        (status = 404, description = "The node is unknown", body = ErrorBody),
+        (status = 503, description = "The manager is not running", body = ErrorBody),
+    )
+)]
+async fn refresh_node(
+    Path(ip): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, ApiError> {
+    let ip = parse_ip(&ip)?;
+    if !state.data_store.contains_node(ip) {
+        return Err(ApiError::node_not_found(ip));
+    }
+    state.manager.refresh(ip).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    post,
+    path = "/discovery/scan",
+    responses(
+        (status = 202, description = "The usage request was broadcast"),
+        (status = 503, description = "The manager is not running", body = ErrorBody),
+    )
+)]
+async fn scan(State(state): State<Arc<AppState>>) -> Result<StatusCode, ApiError> {
+    state.manager.scan().await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// A node that is still running is found again by the next poll.
+#[utoipa::path(
+    delete,
+    path = "/nodes/{ip}",
+    params(("ip" = String, Path, description = "IPv4 address of the node")),
+    responses(
+        (status = 204, description = "The node was removed"),
+        (status = 400, description = "The IP address is invalid", body = ErrorBody),
+        (status = 404, description = "The node is unknown", body = ErrorBody),
+    )
+)]
+async fn forget_node(
+    Path(ip): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, ApiError> {
+    let ip = parse_ip(&ip)?;
+    if state.data_store.remove_node(&ip) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::node_not_found(ip))
+    }
+}
+
+/// The versioned endpoints under `/api/v1`
+fn api_v1() -> axum::Router<Arc<AppState>> {
+    axum::Router::new()
+        .route("/nodes", routing::get(query_nodes))
+        .route("/summary", routing::get(get_summary))
+        .route("/export/inventory", routing::get(export_inventory))
+        .route("/export/usage", routing::get(export_usage))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct NodeListQuery {
+    /// The number of nodes of a page, 50 by default and at most 500
+    limit: Option<usize>,
+    /// The `nextCursor` of the previous page
+    cursor: Option<String>,
+    sort: Option<NodeSort>,
+    /// A part of the OS name ignoring the case, e.g. `Linux`
+    os: Option<String>,
+    /// The architecture ignoring the case, e.g. `x86_64`
+    arch: Option<String>,
+    /// Searches the host name, the IP address and the description
+    q: Option<String>,
+    /// label selector such as `role=db,rack!=r3`
+    selector: Option<String>,
+    group: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/nodes",
+    params(NodeListQuery),
+    responses(
+        (status = 200, description = "A page of the matching nodes", body = return_type::NodesPage),
+        (status = 400, description = "The query, the selector or the cursor is invalid", body = ErrorBody),
+    )
+)]
+async fn query_nodes(
+    ApiQuery(list): ApiQuery<NodeListQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<return_type::NodesPage>, ApiError> {
+    let selector = list
+        .selector
+        .as_deref()
+        .unwrap_or_default()
+        .parse::<LabelSelector>()
+        .map_err(|e| ApiError::bad_request("invalidSelector", e.to_string()))?;
+    let cursor = list
+        .cursor
+        .map(|cursor| cursor.parse::<NodeCursor>())
+        .transpose()
+        .map_err(|e| ApiError::bad_request("invalidCursor", e.to_string()))?;
+    let query = NodeQuery {
+        selector,
+        group: list.group,
+        os: list.os,
+        arch: list.arch,
+        search: list.q.filter(|q| !q.is_empty()),
+        sort: list.sort.unwrap_or_default(),
+        limit: list.limit,
+        cursor,
+    };
+    let page = state
+        .data_store
+        .query_nodes(&query)
+        .map_err(|e| ApiError::bad_request("invalidCursor", e.to_string()))?;
+    Ok(Json(return_type::NodesPage::from(page)))
+}
+
+/// The window in which a recorded machine info counts as a recent change
+const RECENT_CHANGE_WINDOW: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query, rename_all = "camelCase")]
+struct SummaryQuery {
+    /// The key of the label to break the totals down by, e.g. `role`
+    group_by: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/summary",
+    params(SummaryQuery),
+    responses(
+        (status = 200, description = "Totals and breakdowns over the whole fleet", body = FleetSummary),
+        (status = 400, description = "The query is invalid", body = ErrorBody),
+    )
+)]
+async fn get_summary(
+    ApiQuery(query): ApiQuery<SummaryQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Json<FleetSummary> {
+    let changed_since = std::time::SystemTime::now()
+        .checked_sub(RECENT_CHANGE_WINDOW)
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_secs();
+    Json(
+        state
+            .data_store
+            .summary(query.group_by.as_deref(), changed_since),
+    )
+}
+
+/// The window of the usage export when no `from` is given
+const DEFAULT_USAGE_EXPORT_WINDOW: u64 = 60 * 60;
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct InventoryExportQuery {
+    /// `csv` by default
+    format: Option<ExportFormat>,
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UsageExportQuery {
+    /// `csv` by default
+    format: Option<ExportFormat>,
+    /// Unix timestamp in seconds, an hour before `to` by default
+    from: Option<u64>,
+    /// Unix timestamp in seconds, now by default
+    to: Option<u64>,
+}
+
+/// Run an export off the async workers and return it as a download named `name`
+async fn export_response(
+    name: &str,
+    format: ExportFormat,
+    export: impl FnOnce(&mut Vec<u8>) -> Result<(), ExportError> + Send + 'static,
+) -> Result<Response, ApiError> {
+    let body = tokio::task::spawn_blocking(move || {
+        let mut body = Vec::new();
+        export(&mut body).map(|_| body).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|body| body)
+    .map_err(|message| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "exportFailed", message))?;
+    let disposition = format!("attachment; filename=\"{}.{}\"", name, format.extension());
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/export/inventory",
+    params(InventoryExportQuery),
+    responses(
+        (status = 200, description = "One row per node", content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+        )),
+        (status = 400, description = "The query is invalid", body = ErrorBody),
+    )
+)]
+async fn export_inventory(
+    ApiQuery(query): ApiQuery<InventoryExportQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let format = query.format.unwrap_or_default();
+    let data_store = Arc::clone(&state.data_store);
+    export_response("inventory", format, move |body| {
+        data_store.export_inventory(format, body)
+    })
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/export/usage",
+    params(UsageExportQuery),
+    responses(
+        (status = 200, description = "One row per usage record in the window", content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+        )),
+        (status = 400, description = "The query or the window is invalid", body = ErrorBody),
+    )
+)]
+async fn export_usage(
+    ApiQuery(query): ApiQuery<UsageExportQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, ApiError> {
+    let format = query.format.unwrap_or_default();
+    let to = query.to.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    });
+    let from = query
+        .from
+        .unwrap_or_else(|| to.saturating_sub(DEFAULT_USAGE_EXPORT_WINDOW));
+    if from > to {
+        return Err(ApiError::bad_request(
+            "invalidWindow",
+            "`from` is after `to`",
+        ));
+    }
+    let data_store = Arc::clone(&state.data_store);
+    export_response("usage", format, move |body| {
+        data_store.export_usage(from, to, format, body)
+    })
+    .await
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(openapi::ApiDoc::openapi())
 }
 
 mod return_type {
-    use shared::store::data_store::MachineUsageData;
+    use shared::store::data_store::{MachineUsageData, NodeData, NodeOverview, NodeState};
+    use shared::store::labels::NodeLabels;
+    use shared::store::node_query::NodePage;
+
+    fn unix_timestamp(time: std::time::SystemTime) -> u64 {
+        time.duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
 
-    #[derive(Debug, Clone, serde::Serialize)]
+    #[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
     #[serde(rename_all = "camelCase")]
     pub struct NodesData {
+        #[schema(value_type = String, format = Ipv4)]
         ip: std::net::Ipv4Addr,
         machine_info: Option<shared::schemas::device_info::MachineInfo>,
+        #[serde(flatten)]
+        labels: NodeLabels,
+        description: Option<String>,
         usage: Option<shared::schemas::device_info::MachineUsage>,
+        state: NodeState,
+        /// Unix timestamp in seconds
         last_updated: u64,
     }
-    impl NodesData {
-        pub fn new(
-            ip: std::net::Ipv4Addr,
-            machine_info: Option<shared::schemas::device_info::MachineInfo>,
-            usage: Option<shared::schemas::device_info::MachineUsage>,
-            last_updated: u64,
-        ) -> Self {
+    impl From<NodeOverview> for NodesData {
+        fn from(node: NodeOverview) -> Self {
             Self {
-                ip,
-                machine_info,
-                usage,
-                last_updated,
+                ip: node.ip,
+                machine_info: node.machine_info,
+                labels: node.labels,
+                description: node.description,
+                usage: node.usage,
+                state: node.state,
+                last_updated: unix_timestamp(node.last_updated),
             }
         }
     }
 
-    #[derive(Debug, Clone, serde::Serialize)]
+    #[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
     #[serde(rename_all = "camelCase")]
     pub struct Node {
+        #[schema(value_type = String, format = Ipv4)]
         ip: std::net::Ipv4Addr,
         machine_info: Option<shared::schemas::device_info::MachineInfo>,
+        #[serde(flatten)]
+        labels: NodeLabels,
+        description: Option<String>,
         usage: Vec<MachineUsageData>,
+        state: NodeState,
+        /// Unix timestamp in seconds
         last_updated: u64,
     }
-    impl Node {
-        pub fn new(
-            ip: std::net::Ipv4Addr,
-            machine_info: Option<shared::schemas::device_info::MachineInfo>,
-            usage: Vec<MachineUsageData>,
-            last_updated: u64,
-        ) -> Self {
+    impl From<NodeData> for Node {
+        fn from(node: NodeData) -> Self {
+            Self {
+                ip: node.ip,
+                machine_info: node.machine_info,
+                labels: node.labels,
+                description: node.description,
+                usage: node.usage,
+                state: node.state,
+                last_updated: unix_timestamp(node.last_updated),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+    #[serde(rename_all = "camelCase")]
+    pub struct NodesPage {
+        nodes: Vec<NodesData>,
+        /// The number of matching nodes over every page
+        total: usize,
+        /// Pass as `cursor` to get the next page, absent on the last page
+        #[serde(skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
+    }
+    impl From<NodePage> for NodesPage {
+        fn from(page: NodePage) -> Self {
             Self {
-                ip,
-                machine_info,
-                usage,
-                last_updated,
+                nodes: page.nodes.into_iter().map(NodesData::from).collect(),
+                total: page.total,
+                next_cursor: page.next_cursor,
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+    use shared::config::web_server_config::AuthConfig;
+    use shared::schemas::device_info::MachineUsage;
+    use shared::server::manager_server::ManagerServer;
+    use std::net::Ipv4Addr;
+    use tower::ServiceExt;
+
+    const NODE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 5);
+
+    /// A router with one node, authenticating the tokens `viewer`, `operator` and `admin` with
+    /// the role of their name
+    fn app(auth: serde_json::Value) -> (axum::Router, DataStoreType) {
+        let data_store = DataStore::init();
+        data_store.update_usage(NODE, MachineUsage::default());
+        let config = serde_json::from_value::<AuthConfig>(auth).unwrap();
+        let state = Arc::new(AppState {
+            data_store: Arc::clone(&data_store),
+            manager: ManagerServer::new(Arc::clone(&data_store)).handle(),
+            auth: Arc::new(Authenticator::new(&config, None).unwrap()),
+        });
+        (router(state), data_store)
+    }
+
+    fn tokens() -> serde_json::Value {
+        let token = |role: &str| {
+            let hash = Sha256::digest(role)
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>();
+            json!({ "name": role, "sha256": hash, "role": role })
+        };
+        json!({ "tokens": [token("viewer"), token("operator"), token("admin")] })
+    }
+
+    async fn send(
+        router: &axum::Router,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+    ) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn viewers_read_but_do_not_delete() {
+        let (router, data_store) = app(tokens());
+        let node = format!("/nodes/{}", NODE);
+
+        assert_eq!(
+            send(&router, Method::GET, &node, Some("viewer")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&router, Method::DELETE, &node, Some("viewer")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send(&router, Method::DELETE, &node, Some("operator")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert!(data_store.contains_node(NODE));
+    }
+
+    #[tokio::test]
+    async fn admins_delete_nodes() {
+        let (router, data_store) = app(tokens());
+        let node = format!("/nodes/{}", NODE);
+
+        assert_eq!(
+            send(&router, Method::DELETE, &node, Some("admin")).await,
+            StatusCode::NO_CONTENT
+        );
+        assert!(!data_store.contains_node(NODE));
+        assert_eq!(
+            send(&router, Method::DELETE, &node, Some("admin")).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_requests_are_rejected_when_auth_is_configured() {
+        let (router, _) = app(tokens());
+        let node = format!("/nodes/{}", NODE);
+
+        assert_eq!(
+            send(&router, Method::GET, &node, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(&router, Method::GET, &node, Some("unknown")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // the dashboard is public
+        assert_eq!(send(&router, Method::GET, "/", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn clients_are_viewers_without_auth() {
+        let (router, data_store) = app(json!({}));
+        let node = format!("/nodes/{}", NODE);
+
+        assert_eq!(
+            send(&router, Method::GET, &node, None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&router, Method::PUT, &format!("{}/labels", node), None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send(&router, Method::DELETE, &node, None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert!(data_store.contains_node(NODE));
+    }
+}
@@ -0,0 +1,663 @@
+//! `/metrics` in the Prometheus text exposition format.
+//!
+//! Every node is rendered from its latest usage record, identified by the `ip`, `host_name` and
+//! `arch` labels plus its node labels as `label_<key>`. The custom metrics of the nodes are prefixed
+//! with `node_custom_` and get the same identifying labels, a series that a node reports more than
+//! once is rendered with its last value. The manager adds its own health counters.
+
+use crate::AppState;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use shared::schemas::device_info::MachineUsage;
+use shared::server::target_server::DROPPED_REQUESTS_METRIC;
+use shared::store::data_store::{NodeOverview, NodeState};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Arc;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Default)]
+struct Family {
+    help: &'static str,
+    kind: &'static str,
+    samples: Vec<(Labels, f64)>,
+    // the position of each series in `samples`, keyed by its sorted labels
+    series: HashMap<Labels, usize>,
+}
+
+/// The metric families, rendered in the order of their names as the format requires every sample
+/// of a family to be in one group
+#[derive(Debug, Default)]
+struct Exposition {
+    families: BTreeMap<String, Family>,
+}
+
+impl Exposition {
+    fn add(
+        &mut self,
+        name: &str,
+        kind: &'static str,
+        help: &'static str,
+        labels: Labels,
+        value: f64,
+    ) {
+        let family = self
+            .families
+            .entry(name.to_string())
+            .or_insert_with(|| Family {
+                help,
+                kind,
+                ..Family::default()
+            });
+        let mut key = labels.clone();
+        key.sort_unstable();
+        match family.series.get(&key) {
+            // a series is only rendered once, e.g. a metric of both a textfile and a script
+            Some(&index) => family.samples[index].1 = value,
+            None => {
+                family.series.insert(key, family.samples.len());
+                family.samples.push((labels, value));
+            }
+        }
+    }
+
+    fn gauge(&mut self, name: &str, help: &'static str, labels: &Labels, value: f64) {
+        self.add(name, "gauge", help, labels.clone(), value);
+    }
+
+    /// A gauge with labels on top of the labels of the node
+    fn gauge_with(
+        &mut self,
+        name: &str,
+        help: &'static str,
+        labels: &Labels,
+        extra: &[(&str, &str)],
+        value: f64,
+    ) {
+        let mut labels = labels.clone();
+        labels.extend(
+            extra
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        self.add(name, "gauge", help, labels, value);
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+        for (name, family) in self.families.iter() {
+            if !family.help.is_empty() {
+                let _ = writeln!(output, "# HELP {} {}", name, family.help);
+            }
+            let _ = writeln!(output, "# TYPE {} {}", name, family.kind);
+            for (labels, value) in family.samples.iter() {
+                output.push_str(name);
+                if !labels.is_empty() {
+                    let labels = labels
+                        .iter()
+                        .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    let _ = write!(output, "{{{}}}", labels);
+                }
+                let _ = writeln!(output, " {}", format_value(*value));
+            }
+        }
+        output
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value.is_infinite() {
+        String::from(if value > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        value.to_string()
+    }
+}
+
+/// Replace the characters that are not allowed in a metric or label name
+fn sanitize(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// Add a label, a name that is already taken, e.g. by two keys that are the same after
+/// [`sanitize`], gets a numbered suffix
+fn push_label(labels: &mut Labels, name: String, value: String) {
+    let mut unique = name.clone();
+    let mut suffix = 2;
+    while labels.iter().any(|(label, _)| *label == unique) {
+        unique = format!("{}_{}", name, suffix);
+        suffix += 1;
+    }
+    labels.push((unique, value));
+}
+
+fn node_labels(node: &NodeOverview) -> Labels {
+    let mut labels = vec![(String::from("ip"), node.ip.to_string())];
+    if let Some(machine_info) = &node.machine_info {
+        labels.push((String::from("host_name"), machine_info.host_name.clone()));
+        labels.push((String::from("arch"), machine_info.arch.clone()));
+    }
+    for (key, value) in node.labels.labels.iter() {
+        push_label(
+            &mut labels,
+            format!("label_{}", sanitize(key)),
+            value.clone(),
+        );
+    }
+    labels
+}
+
+fn add_usage(exposition: &mut Exposition, labels: &Labels, usage: &MachineUsage) {
+    exposition.gauge(
+        "discovery_node_memory_total_bytes",
+        "Total memory of the node",
+        labels,
+        usage.total_memory as f64,
+    );
+    exposition.gauge(
+        "discovery_node_memory_used_bytes",
+        "Used memory of the node",
+        labels,
+        usage.used_memory as f64,
+    );
+    exposition.gauge(
+        "discovery_node_swap_total_bytes",
+        "Total swap of the node",
+        labels,
+        usage.total_swap as f64,
+    );
+    exposition.gauge(
+        "discovery_node_swap_used_bytes",
+        "Used swap of the node",
+        labels,
+        usage.used_swap as f64,
+    );
+    for (cpu, (usage, frequency)) in usage
+        .cpu_usage
+        .iter()
+        .zip(usage.cpu_frequency.iter())
+        .enumerate()
+    {
+        let cpu = cpu.to_string();
+        exposition.gauge_with(
+            "discovery_node_cpu_usage_percent",
+            "Usage of a logical CPU",
+            labels,
+            &[("cpu", &cpu)],
+            f64::from(*usage),
+        );
+        exposition.gauge_with(
+            "discovery_node_cpu_frequency_megahertz",
+            "Frequency of a logical CPU",
+            labels,
+            &[("cpu", &cpu)],
+            *frequency as f64,
+        );
+    }
+    if let (Some(cpus), Some(cpu_usage)) = (usage.effective_cpus, usage.effective_cpu_usage) {
+        exposition.gauge(
+            "discovery_node_effective_cpus",
+            "CPUs the container of the node can use",
+            labels,
+            f64::from(cpus),
+        );
+        exposition.gauge(
+            "discovery_node_effective_cpu_usage_percent",
+            "CPU usage of the container of the node in percent of its effective CPUs",
+            labels,
+            f64::from(cpu_usage),
+        );
+    }
+    for interface in usage.interfaces.iter() {
+        let extra = [("interface", interface.name.as_str())];
+        exposition.gauge_with(
+            "discovery_node_network_receive_bytes_per_second",
+            "Bytes received per second by an interface",
+            labels,
+            &extra,
+            interface.received_bytes_per_second,
+        );
+        exposition.gauge_with(
+            "discovery_node_network_transmit_bytes_per_second",
+            "Bytes transmitted per second by an interface",
+            labels,
+            &extra,
+            interface.transmitted_bytes_per_second,
+        );
+    }
+    exposition.gauge(
+        "discovery_node_load1",
+        "1 minute load average",
+        labels,
+        usage.load_average.one,
+    );
+    exposition.gauge(
+        "discovery_node_load5",
+        "5 minute load average",
+        labels,
+        usage.load_average.five,
+    );
+    exposition.gauge(
+        "discovery_node_load15",
+        "15 minute load average",
+        labels,
+        usage.load_average.fifteen,
+    );
+    exposition.gauge(
+        "discovery_node_uptime_seconds",
+        "Seconds since the node booted",
+        labels,
+        usage.uptime as f64,
+    );
+    exposition.gauge(
+        "discovery_node_processes",
+        "Number of processes",
+        labels,
+        usage.process_count as f64,
+    );
+    exposition.gauge(
+        "discovery_node_threads",
+        "Number of threads",
+        labels,
+        usage.thread_count as f64,
+    );
+    for reading in usage.temperatures.iter() {
+        exposition.gauge_with(
+            "discovery_node_temperature_celsius",
+            "Temperature of a sensor",
+            labels,
+            &[("sensor", &reading.label)],
+            f64::from(reading.temperature),
+        );
+    }
+    for filesystem in usage.filesystems.iter() {
+        let extra = [
+            ("mountpoint", filesystem.mount_point.as_str()),
+            ("device", filesystem.device.as_str()),
+            ("fstype", filesystem.file_system.as_str()),
+        ];
+        exposition.gauge_with(
+            "discovery_node_filesystem_size_bytes",
+            "Capacity of a filesystem",
+            labels,
+            &extra,
+            filesystem.total_space as f64,
+        );
+        exposition.gauge_with(
+            "discovery_node_filesystem_available_bytes",
+            "Bytes of a filesystem available to unprivileged users",
+            labels,
+            &extra,
+            filesystem.available_space as f64,
+        );
+    }
+    for disk in usage.disk_io.iter() {
+        let extra = [("device", disk.device.as_str())];
+        exposition.gauge_with(
+            "discovery_node_disk_read_bytes_per_second",
+            "Bytes read per second from a block device",
+            labels,
+            &extra,
+            disk.read_bytes_per_second,
+        );
+        exposition.gauge_with(
+            "discovery_node_disk_write_bytes_per_second",
+            "Bytes written per second to a block device",
+            labels,
+            &extra,
+            disk.write_bytes_per_second,
+        );
+    }
+    for container in usage.containers.iter() {
+        let extra = [
+            ("container_id", container.id.as_str()),
+            ("runtime", container.runtime.as_str()),
+        ];
+        exposition.gauge_with(
+            "discovery_node_container_cpu_usage_percent",
+            "CPU usage of a container in percent of a single CPU",
+            labels,
+            &extra,
+            f64::from(container.cpu_usage),
+        );
+        if let Some(cpu_limit_usage) = container.cpu_limit_usage {
+            exposition.gauge_with(
+                "discovery_node_container_cpu_limit_usage_percent",
+                "CPU usage of a container in percent of its CPU limit",
+                labels,
+                &extra,
+                f64::from(cpu_limit_usage),
+            );
+        }
+        exposition.gauge_with(
+            "discovery_node_container_memory_usage_bytes",
+            "Memory usage of a container",
+            labels,
+            &extra,
+            container.memory_usage as f64,
+        );
+    }
+    for metric in usage.metrics.iter() {
+        // the labels of the metric are kept, a clash with a node label is renamed
+        let mut metric_labels = labels.clone();
+        for (key, value) in metric.labels.iter() {
+            let key = sanitize(key);
+            let key = if labels.iter().any(|(label, _)| *label == key) {
+                format!("exported_{}", key)
+            } else {
+                key
+            };
+            push_label(&mut metric_labels, key, value.clone());
+        }
+        if metric.name == DROPPED_REQUESTS_METRIC {
+            exposition.add(
+                "discovery_node_dropped_requests_total",
+                "counter",
+                "Requests the node dropped, by reason",
+                metric_labels,
+                metric.value,
+            );
+        } else {
+            // the prefix keeps the custom metrics out of the families above
+            exposition.add(
+                &format!("node_custom_{}", sanitize(&metric.name)),
+                "untyped",
+                "",
+                metric_labels,
+                metric.value,
+            );
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "The nodes and the manager in the Prometheus text format", body = String, content_type = "text/plain"),
+    )
+)]
+pub(crate) async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut exposition = Exposition::default();
+    let nodes = state.data_store.get_node_overview();
+
+    let mut online = 0;
+    for node in nodes.iter() {
+        let labels = node_labels(node);
+        let up = matches!(node.state, NodeState::Online);
+        if up {
+            online += 1;
+        }
+        exposition.gauge(
+            "discovery_node_up",
+            "Whether the node answers the manager",
+            &labels,
+            if up { 1.0 } else { 0.0 },
+        );
+        if let Some(usage) = &node.usage {
+            add_usage(&mut exposition, &labels, usage);
+        }
+    }
+
+    let counters = state.manager.counters();
+    for (name, help, value) in [
+        (
+            "discovery_manager_polls_sent_total",
+            "Usage requests broadcast to the nodes",
+            counters.polls_sent,
+        ),
+        (
+            "discovery_manager_requests_sent_total",
+            "Requests sent to a single node",
+            counters.requests_sent,
+        ),
+        (
+            "discovery_manager_send_failures_total",
+            "Requests that could not be sent",
+            counters.send_failures,
+        ),
+        (
+            "discovery_manager_replies_parsed_total",
+            "Replies received from the nodes",
+            counters.replies_parsed,
+        ),
+        (
+            "discovery_manager_parse_failures_total",
+            "Replies that could not be parsed",
+            counters.parse_failures,
+        ),
+    ] {
+        exposition.add(name, "counter", help, Vec::new(), value as f64);
+    }
+    exposition.gauge(
+        "discovery_manager_nodes_online",
+        "Nodes that answer the manager",
+        &Vec::new(),
+        f64::from(online),
+    );
+    exposition.gauge(
+        "discovery_manager_nodes",
+        "Nodes known to the manager",
+        &Vec::new(),
+        nodes.len() as f64,
+    );
+
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], exposition.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::schemas::device_info::{MachineInfo, Metric};
+    use shared::store::labels::NodeLabels;
+    use std::net::Ipv4Addr;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// The sample lines of a family, without the HELP and TYPE comments
+    fn samples<'a>(output: &'a str, name: &str) -> Vec<&'a str> {
+        output
+            .lines()
+            .filter(|line| {
+                line.starts_with(&format!("{}{{", name)) || line.starts_with(&format!("{} ", name))
+            })
+            .collect()
+    }
+
+    fn usage_with(metrics: Vec<Metric>) -> String {
+        let mut exposition = Exposition::default();
+        let usage = MachineUsage {
+            metrics,
+            ..MachineUsage::default()
+        };
+        add_usage(&mut exposition, &labels(&[("ip", "10.0.0.5")]), &usage);
+        exposition.render()
+    }
+
+    #[test]
+    fn families_are_rendered_in_groups_by_name() {
+        let mut exposition = Exposition::default();
+        exposition.gauge(
+            "b_metric",
+            "The second",
+            &labels(&[("ip", "10.0.0.6")]),
+            2.0,
+        );
+        exposition.add("a_total", "counter", "", Vec::new(), 7.0);
+        exposition.gauge(
+            "b_metric",
+            "The second",
+            &labels(&[("ip", "10.0.0.5")]),
+            1.5,
+        );
+        assert_eq!(
+            exposition.render(),
+            "# TYPE a_total counter\n\
+             a_total 7\n\
+             # HELP b_metric The second\n\
+             # TYPE b_metric gauge\n\
+             b_metric{ip=\"10.0.0.6\"} 2\n\
+             b_metric{ip=\"10.0.0.5\"} 1.5\n"
+        );
+    }
+
+    #[test]
+    fn special_values_and_label_values_are_escaped() {
+        let mut exposition = Exposition::default();
+        let escaped = labels(&[("path", "C:\\temp \"new\"\nline")]);
+        exposition.gauge("nan", "", &escaped, f64::NAN);
+        exposition.gauge("inf", "", &Vec::new(), f64::INFINITY);
+        exposition.gauge("negative_inf", "", &Vec::new(), f64::NEG_INFINITY);
+        let output = exposition.render();
+        assert_eq!(
+            samples(&output, "nan"),
+            ["nan{path=\"C:\\\\temp \\\"new\\\"\\nline\"} NaN"]
+        );
+        assert_eq!(samples(&output, "inf"), ["inf +Inf"]);
+        assert_eq!(samples(&output, "negative_inf"), ["negative_inf -Inf"]);
+    }
+
+    #[test]
+    fn names_are_sanitized() {
+        assert_eq!(sanitize("disk.io-time"), "disk_io_time");
+        assert_eq!(sanitize("2xx_responses"), "_2xx_responses");
+        assert_eq!(sanitize("température"), "temp_rature");
+        assert_eq!(sanitize("already_valid_1"), "already_valid_1");
+    }
+
+    #[test]
+    fn taken_label_names_get_a_suffix() {
+        let mut taken = labels(&[("label_team", "a")]);
+        push_label(&mut taken, String::from("label_team"), String::from("b"));
+        push_label(&mut taken, String::from("label_team"), String::from("c"));
+        push_label(&mut taken, String::from("label_zone"), String::from("d"));
+        assert_eq!(
+            taken,
+            labels(&[
+                ("label_team", "a"),
+                ("label_team_2", "b"),
+                ("label_team_3", "c"),
+                ("label_zone", "d"),
+            ])
+        );
+    }
+
+    #[test]
+    fn node_labels_identify_the_node() {
+        let node = NodeOverview {
+            ip: Ipv4Addr::new(10, 0, 0, 5),
+            machine_info: Some(MachineInfo {
+                host_name: String::from("web-1"),
+                arch: String::from("x86_64"),
+                ..MachineInfo::default()
+            }),
+            labels: NodeLabels {
+                labels: BTreeMap::from([
+                    (String::from("team.name"), String::from("infra")),
+                    (String::from("team-name"), String::from("ops")),
+                ]),
+                ..NodeLabels::default()
+            },
+            description: None,
+            usage: None,
+            state: NodeState::Online,
+            last_updated: std::time::SystemTime::now(),
+        };
+        assert_eq!(
+            node_labels(&node),
+            labels(&[
+                ("ip", "10.0.0.5"),
+                ("host_name", "web-1"),
+                ("arch", "x86_64"),
+                ("label_team_name", "ops"),
+                ("label_team_name_2", "infra"),
+            ])
+        );
+    }
+
+    #[test]
+    fn custom_metrics_are_prefixed() {
+        let output = usage_with(vec![
+            Metric::new("queue.depth", 3.0).with_label("queue", "mail"),
+            Metric::new(DROPPED_REQUESTS_METRIC, 2.0).with_label("reason", "busy"),
+        ]);
+        assert!(output.contains("# TYPE node_custom_queue_depth untyped\n"));
+        assert_eq!(
+            samples(&output, "node_custom_queue_depth"),
+            ["node_custom_queue_depth{ip=\"10.0.0.5\",queue=\"mail\"} 3"]
+        );
+        assert_eq!(
+            samples(&output, "discovery_node_dropped_requests_total"),
+            ["discovery_node_dropped_requests_total{ip=\"10.0.0.5\",reason=\"busy\"} 2"]
+        );
+    }
+
+    #[test]
+    fn custom_labels_clashing_with_node_labels_are_renamed() {
+        let output = usage_with(vec![
+            Metric::new("jobs", 1.0).with_label("ip", "192.168.1.1"),
+        ]);
+        assert_eq!(
+            samples(&output, "node_custom_jobs"),
+            ["node_custom_jobs{ip=\"10.0.0.5\",exported_ip=\"192.168.1.1\"} 1"]
+        );
+    }
+
+    #[test]
+    fn duplicate_series_keep_the_last_value() {
+        let output = usage_with(vec![
+            Metric::new("jobs", 1.0).with_label("queue", "mail"),
+            Metric::new("jobs", 2.0).with_label("queue", "print"),
+            Metric::new("jobs", 3.0).with_label("queue", "mail"),
+            // the same series once sanitized
+            Metric::new("jobs", 4.0).with_label("queue.", "print"),
+            Metric::new("jobs", 5.0).with_label("queue_", "print"),
+        ]);
+        assert_eq!(
+            samples(&output, "node_custom_jobs"),
+            [
+                "node_custom_jobs{ip=\"10.0.0.5\",queue=\"mail\"} 3",
+                "node_custom_jobs{ip=\"10.0.0.5\",queue=\"print\"} 2",
+                "node_custom_jobs{ip=\"10.0.0.5\",queue_=\"print\"} 5",
+            ]
+        );
+    }
+
+    #[test]
+    fn series_are_identified_by_their_sorted_labels() {
+        let mut exposition = Exposition::default();
+        exposition.gauge("jobs", "", &labels(&[("a", "1"), ("b", "2")]), 1.0);
+        exposition.gauge("jobs", "", &labels(&[("b", "2"), ("a", "1")]), 2.0);
+        assert_eq!(
+            samples(&exposition.render(), "jobs"),
+            ["jobs{a=\"1\",b=\"2\"} 2"]
+        );
+    }
+}
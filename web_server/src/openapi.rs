@@ -0,0 +1,39 @@
+//! The OpenAPI document served at `/openapi.json`.
+
+use crate::error::ErrorBody;
+use crate::{events, metrics, return_type};
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Network Discovery",
+        description = "The nodes found by the manager"
+    ),
+    paths(
+        crate::node_overview,
+        crate::get_node,
+        crate::get_spec_history,
+        crate::set_labels,
+        crate::set_groups,
+        crate::get_processes,
+        crate::refresh_node,
+        crate::scan,
+        crate::forget_node,
+        crate::get_groups,
+        crate::query_nodes,
+        crate::get_summary,
+        crate::export_inventory,
+        crate::export_usage,
+        events::sse,
+        events::websocket,
+        metrics::metrics,
+    ),
+    components(schemas(
+        return_type::NodesData,
+        return_type::Node,
+        return_type::NodesPage,
+        ErrorBody
+    ))
+)]
+pub(crate) struct ApiDoc;
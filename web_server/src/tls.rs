@@ -0,0 +1,478 @@
+//! HTTPS termination.
+//!
+//! The certificate, the key and the CA file of the client certificates are checked for changes
+//! periodically and loaded without a restart, so a renewed certificate is picked up on its own.
+//! When mutual TLS is configured, the fingerprint of the client certificate of a connection is
+//! added to each of its requests, so the authentication can grant it a role.
+
+use axum::Router;
+use axum::extract::Request;
+use axum::http::{StatusCode, Uri, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum_server::accept::{Accept, DefaultAcceptor};
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use rustls::RootCertStore;
+use rustls::ServerConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use sha2::{Digest, Sha256};
+use shared::config::web_server_config::TlsConfig;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower::Layer;
+use tracing::{error, info};
+
+/// The certificate a client presented, `None` on connections without one
+#[derive(Debug, Clone)]
+pub(crate) struct ClientCertificate {
+    /// The hex encoded SHA-256 hash of the DER encoded certificate
+    pub(crate) fingerprint: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct TlsError(String);
+
+impl std::fmt::Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid TLS config: {}", self.0)
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+fn load_server_config(config: &TlsConfig) -> Result<ServerConfig, TlsError> {
+    let certificates = CertificateDer::pem_file_iter(&config.certificate)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError(format!("failed to read {:?}: {}", config.certificate, e)))?;
+    if certificates.is_empty() {
+        return Err(TlsError(format!(
+            "{:?} has no certificate",
+            config.certificate
+        )));
+    }
+    let key = PrivateKeyDer::from_pem_file(&config.key)
+        .map_err(|e| TlsError(format!("failed to read {:?}: {}", config.key, e)))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| TlsError(e.to_string()))?;
+    let builder = match &config.client_auth {
+        Some(client_auth) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in
+                CertificateDer::pem_file_iter(&client_auth.ca_certificates).map_err(|e| {
+                    TlsError(format!(
+                        "failed to read {:?}: {}",
+                        client_auth.ca_certificates, e
+                    ))
+                })?
+            {
+                let certificate = certificate.map_err(|e| TlsError(e.to_string()))?;
+                roots
+                    .add(certificate)
+                    .map_err(|e| TlsError(e.to_string()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if client_auth.required {
+                verifier
+            } else {
+                verifier.allow_unauthenticated()
+            };
+            builder
+                .with_client_cert_verifier(verifier.build().map_err(|e| TlsError(e.to_string()))?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder
+        .with_single_cert(certificates, key)
+        .map_err(|e| TlsError(e.to_string()))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+/// The files the server config is loaded from
+fn watched_files(config: &TlsConfig) -> Vec<PathBuf> {
+    let mut files = vec![config.certificate.clone(), config.key.clone()];
+    if let Some(client_auth) = &config.client_auth {
+        files.push(client_auth.ca_certificates.clone());
+    }
+    files
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| {
+            std::fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Reload the server config whenever one of its files changes
+async fn reload_on_change(config: TlsConfig, rustls_config: RustlsConfig) {
+    let files = watched_files(&config);
+    let mut last_modified = modified_times(&files);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.reload_interval.max(1)));
+    loop {
+        interval.tick().await;
+        reload_if_changed(&config, &rustls_config, &files, &mut last_modified);
+    }
+}
+
+/// Reload the server config if one of its files was modified since `last_modified`.
+/// Returns whether the config was reloaded.
+fn reload_if_changed(
+    config: &TlsConfig,
+    rustls_config: &RustlsConfig,
+    files: &[PathBuf],
+    last_modified: &mut Vec<Option<SystemTime>>,
+) -> bool {
+    let modified = modified_times(files);
+    if modified == *last_modified {
+        return false;
+    }
+    *last_modified = modified;
+    match load_server_config(config) {
+        Ok(server_config) => {
+            rustls_config.reload_from_config(Arc::new(server_config));
+            info!("Reloaded the TLS certificate from {:?}", config.certificate);
+            true
+        }
+        // e.g. the certificate was written before the key, the next change is tried again
+        Err(e) => {
+            error!("Keeping the previous TLS certificate: {}", e);
+            false
+        }
+    }
+}
+
+/// Completes the TLS handshake and adds the client certificate to the requests of the connection
+#[derive(Clone)]
+struct ClientCertificateAcceptor {
+    inner: RustlsAcceptor<DefaultAcceptor>,
+}
+
+impl<I, S> Accept<I, S> for ClientCertificateAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = axum::middleware::AddExtension<S, Option<ClientCertificate>>;
+    type Future =
+        Pin<Box<dyn Future<Output = std::io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certificates| certificates.first())
+                .map(|certificate| ClientCertificate {
+                    fingerprint: Sha256::digest(certificate)
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect(),
+                });
+            Ok((stream, axum::Extension(certificate).layer(service)))
+        })
+    }
+}
+
+/// Serve the app over HTTPS, plus the redirect from plain HTTP if configured
+///
+/// # Panics
+///
+/// Panics if the certificate or the key cannot be loaded at startup, or if the address of the
+/// redirect cannot be bound.
+pub(crate) async fn serve(app: Router, listen: SocketAddr, config: TlsConfig) {
+    let server_config = load_server_config(&config).unwrap_or_else(|e| panic!("{e}"));
+    let rustls_config = RustlsConfig::from_config(Arc::new(server_config));
+    tokio::spawn(reload_on_change(config.clone(), rustls_config.clone()));
+
+    if let Some(redirect) = config.http_redirect {
+        // bound before spawning, so a taken port stops the startup instead of a background task
+        let listener = tokio::net::TcpListener::bind(redirect)
+            .await
+            .unwrap_or_else(|e| panic!("failed to bind the HTTP redirect to {}: {}", redirect, e));
+        info!("Redirecting HTTP on {} to HTTPS", redirect);
+        tokio::spawn(serve_redirect(listener, listen.port()));
+    }
+
+    info!("Serving HTTPS on {}", listen);
+    axum_server::bind(listen)
+        .acceptor(ClientCertificateAcceptor {
+            inner: RustlsAcceptor::new(rustls_config),
+        })
+        .serve(app.into_make_service())
+        .await
+        .unwrap();
+}
+
+/// Answer every plain HTTP request with a redirect to the same URL over HTTPS
+async fn serve_redirect(listener: tokio::net::TcpListener, https_port: u16) {
+    let app = Router::new()
+        .fallback(move |request: Request| async move { redirect_to_https(request, https_port) });
+    if let Err(e) = axum::serve(listener, app).await {
+        error!("The HTTP redirect stopped: {}", e);
+    }
+}
+
+fn redirect_to_https(request: Request, https_port: u16) -> Response {
+    let Some(host) = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    // drop the port of the HTTP listener, an IPv6 address keeps its brackets
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    let authority = if https_port == 443 {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, https_port)
+    };
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    match format!("https://{}{}", authority, path).parse::<Uri>() {
+        Ok(uri) => Redirect::permanent(&uri.to_string()).into_response(),
+        Err(_) => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use shared::config::web_server_config::{ClientAuthConfig, Role};
+    use std::path::Path;
+
+    // a CA and a server certificate it issued for `localhost`, valid for 100 years
+    const CERTIFICATES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/certs");
+
+    /// A copy of the test certificates the test may change, removed when dropped
+    struct CertificateDir(PathBuf);
+
+    impl CertificateDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "network-discovery-tls-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            for file in ["ca.pem", "server.pem", "server.key"] {
+                std::fs::copy(Path::new(CERTIFICATES).join(file), path.join(file)).unwrap();
+            }
+            Self(path)
+        }
+
+        fn config(&self) -> TlsConfig {
+            TlsConfig {
+                certificate: self.0.join("server.pem"),
+                key: self.0.join("server.key"),
+                reload_interval: 1,
+                http_redirect: None,
+                client_auth: None,
+            }
+        }
+
+        fn client_auth(&self, ca_certificates: &str) -> Option<ClientAuthConfig> {
+            Some(ClientAuthConfig {
+                ca_certificates: self.0.join(ca_certificates),
+                required: true,
+                role: Role::Viewer,
+            })
+        }
+
+        /// Replace a file and move its modification time forward, as a renewal would
+        fn replace(&self, file: &str, content: &str) {
+            let path = self.0.join(file);
+            std::fs::write(&path, content).unwrap();
+            let modified = SystemTime::now() + Duration::from_secs(60);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+    }
+
+    impl Drop for CertificateDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn load_error(config: &TlsConfig) -> String {
+        load_server_config(config).unwrap_err().to_string()
+    }
+
+    fn redirect(host: Option<&str>, path: &str, https_port: u16) -> (StatusCode, Option<String>) {
+        let mut request = Request::builder().uri(path);
+        if let Some(host) = host {
+            request = request.header(header::HOST, host);
+        }
+        let response = redirect_to_https(request.body(Body::empty()).unwrap(), https_port);
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .map(|location| location.to_str().unwrap().to_string());
+        (response.status(), location)
+    }
+
+    #[test]
+    fn redirects_keep_the_host_and_the_path() {
+        let permanent = StatusCode::PERMANENT_REDIRECT;
+        assert_eq!(
+            redirect(Some("example.com"), "/api/v1/nodes?limit=5", 443),
+            (
+                permanent,
+                Some(String::from("https://example.com/api/v1/nodes?limit=5"))
+            )
+        );
+        assert_eq!(
+            redirect(Some("example.com:8080"), "/", 8443),
+            (permanent, Some(String::from("https://example.com:8443/")))
+        );
+        assert_eq!(
+            redirect(Some("10.0.0.5:80"), "/metrics", 443),
+            (permanent, Some(String::from("https://10.0.0.5/metrics")))
+        );
+    }
+
+    #[test]
+    fn redirects_keep_the_brackets_of_ipv6_hosts() {
+        let permanent = StatusCode::PERMANENT_REDIRECT;
+        assert_eq!(
+            redirect(Some("[::1]"), "/", 8443),
+            (permanent, Some(String::from("https://[::1]:8443/")))
+        );
+        assert_eq!(
+            redirect(Some("[::1]:8080"), "/", 443),
+            (permanent, Some(String::from("https://[::1]/")))
+        );
+    }
+
+    #[test]
+    fn requests_without_a_valid_host_are_rejected() {
+        assert_eq!(redirect(None, "/", 443), (StatusCode::BAD_REQUEST, None));
+        assert_eq!(
+            redirect(Some("bad host"), "/", 443),
+            (StatusCode::BAD_REQUEST, None)
+        );
+    }
+
+    #[test]
+    fn server_config_is_loaded() {
+        let dir = CertificateDir::new("load");
+        let server_config = load_server_config(&dir.config()).unwrap();
+        assert_eq!(
+            server_config.alpn_protocols,
+            [b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+
+        let config = TlsConfig {
+            client_auth: dir.client_auth("ca.pem"),
+            ..dir.config()
+        };
+        assert!(load_server_config(&config).is_ok());
+    }
+
+    #[test]
+    fn bad_pem_files_are_rejected() {
+        let dir = CertificateDir::new("bad-pem");
+        dir.replace("server.pem", "not a certificate\n");
+        assert!(load_error(&dir.config()).ends_with("has no certificate"));
+
+        dir.replace(
+            "server.pem",
+            "-----BEGIN CERTIFICATE-----\n!!!\n-----END CERTIFICATE-----\n",
+        );
+        assert!(load_error(&dir.config()).contains("failed to read"));
+
+        let dir = CertificateDir::new("bad-key");
+        dir.replace("server.key", "not a key\n");
+        assert!(load_error(&dir.config()).contains("failed to read"));
+    }
+
+    #[test]
+    fn missing_client_ca_is_rejected() {
+        let dir = CertificateDir::new("client-ca");
+        let config = TlsConfig {
+            client_auth: dir.client_auth("missing.pem"),
+            ..dir.config()
+        };
+        assert!(load_error(&config).contains("missing.pem"));
+
+        // a file without a CA leaves nothing to verify the clients against
+        dir.replace("empty.pem", "");
+        let config = TlsConfig {
+            client_auth: dir.client_auth("empty.pem"),
+            ..dir.config()
+        };
+        assert!(load_server_config(&config).is_err());
+    }
+
+    #[test]
+    fn changed_files_are_reloaded() {
+        let dir = CertificateDir::new("reload");
+        let config = dir.config();
+        let rustls_config =
+            RustlsConfig::from_config(Arc::new(load_server_config(&config).unwrap()));
+        let files = watched_files(&config);
+        let mut last_modified = modified_times(&files);
+        let loaded = rustls_config.get_inner();
+
+        assert!(!reload_if_changed(
+            &config,
+            &rustls_config,
+            &files,
+            &mut last_modified
+        ));
+        assert!(Arc::ptr_eq(&loaded, &rustls_config.get_inner()));
+
+        let certificate = std::fs::read_to_string(&config.certificate).unwrap();
+        dir.replace("server.pem", &certificate);
+        assert!(reload_if_changed(
+            &config,
+            &rustls_config,
+            &files,
+            &mut last_modified
+        ));
+        let reloaded = rustls_config.get_inner();
+        assert!(!Arc::ptr_eq(&loaded, &reloaded));
+
+        // a broken key keeps the previous config
+        dir.replace("server.key", "not a key\n");
+        assert!(!reload_if_changed(
+            &config,
+            &rustls_config,
+            &files,
+            &mut last_modified
+        ));
+        assert!(Arc::ptr_eq(&reloaded, &rustls_config.get_inner()));
+    }
+}
@@ -0,0 +1,320 @@
+// The dashboard of web_server, built on the JSON endpoints only.
+// `#/` shows the fleet, `#/nodes/<ip>` a single node. Both views refresh every few seconds.
+"use strict";
+
+const REFRESH_INTERVAL = 5000;
+const PAGE_LIMIT = 50;
+
+const $ = (id) => document.getElementById(id);
+
+let refreshTimer = null;
+// the nodes loaded so far and the cursor of the next page
+let listing = { nodes: [], cursor: null, total: 0 };
+
+async function fetchJson(path) {
+  const response = await fetch(path, { headers: { Accept: "application/json" } });
+  const body = await response.json().catch(() => null);
+  if (!response.ok) {
+    throw new Error(body && body.message ? body.message : `${response.status} ${response.statusText}`);
+  }
+  return body;
+}
+
+function element(tag, attributes = {}, ...children) {
+  const node = document.createElement(tag);
+  for (const [key, value] of Object.entries(attributes)) {
+    if (key === "class") {
+      node.className = value;
+    } else {
+      node.setAttribute(key, value);
+    }
+  }
+  for (const child of children) {
+    node.append(child instanceof Node ? child : document.createTextNode(child ?? ""));
+  }
+  return node;
+}
+
+function formatBytes(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit += 1;
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
+function formatPercent(value) {
+  return value == null ? "-" : `${value.toFixed(1)} %`;
+}
+
+function formatTime(seconds) {
+  return new Date(seconds * 1000).toLocaleString();
+}
+
+// a node inside a container reports its usage of the CPUs it can use
+function averageCpu(usage) {
+  if (usage && usage.effectiveCpuUsage != null) {
+    return usage.effectiveCpuUsage;
+  }
+  if (!usage || usage.cpuUsage.length === 0) {
+    return null;
+  }
+  return usage.cpuUsage.reduce((sum, cpu) => sum + cpu, 0) / usage.cpuUsage.length;
+}
+
+function memoryPercent(usage) {
+  if (!usage || usage.totalMemory === 0) {
+    return null;
+  }
+  return (usage.usedMemory / usage.totalMemory) * 100;
+}
+
+function bar(percent) {
+  const width = Math.max(0, Math.min(100, percent ?? 0));
+  return element(
+    "span",
+    {},
+    element("span", { class: "bar" }, element("span", { style: `width: ${width}%` })),
+    formatPercent(percent),
+  );
+}
+
+function showError(error) {
+  $("error").hidden = error == null;
+  $("error").textContent = error ? `Failed to load: ${error.message}` : "";
+}
+
+function markUpdated() {
+  $("updated").textContent = `Updated ${new Date().toLocaleTimeString()}`;
+}
+
+// ---- fleet overview ----
+
+function card(label, value) {
+  return element(
+    "div",
+    { class: "card" },
+    element("div", { class: "value" }, value),
+    element("div", { class: "label" }, label),
+  );
+}
+
+async function loadSummary() {
+  const summary = await fetchJson("/api/v1/summary");
+  $("summary").replaceChildren(
+    card("Nodes", String(summary.nodeCount)),
+    card("Online", String(summary.byState.online)),
+    card("Offline", String(summary.byState.offline)),
+    card("Average CPU", formatPercent(summary.cpu.average)),
+    card("p95 CPU", formatPercent(summary.cpu.p95)),
+    card(
+      "Memory used",
+      `${formatBytes(summary.memory.usedMemory)} / ${formatBytes(summary.memory.totalMemory)}`,
+    ),
+    card("Changed in the last hour", String(summary.recentlyChanged.length)),
+  );
+}
+
+function listQuery(cursor) {
+  const form = new FormData($("filters"));
+  const params = new URLSearchParams({ limit: String(PAGE_LIMIT) });
+  for (const [key, value] of form.entries()) {
+    if (value) {
+      params.set(key, value);
+    }
+  }
+  if (cursor) {
+    params.set("cursor", cursor);
+  }
+  return `/api/v1/nodes?${params}`;
+}
+
+function nodeRow(node) {
+  const info = node.machineInfo;
+  const link = element("a", { href: `#/nodes/${node.ip}` }, info ? info.hostName : node.ip);
+  return element(
+    "tr",
+    {},
+    element("td", {}, element("span", { class: `state ${node.state}`, title: node.state })),
+    element("td", {}, link),
+    element("td", {}, node.ip),
+    element("td", {}, info ? `${info.os} ${info.osVersion}` : "-"),
+    element("td", {}, info ? info.arch : "-"),
+    element("td", {}, bar(averageCpu(node.usage))),
+    element("td", {}, bar(memoryPercent(node.usage))),
+    element("td", {}, formatTime(node.lastUpdated)),
+  );
+}
+
+function renderListing() {
+  $("nodes").replaceChildren(...listing.nodes.map(nodeRow));
+  $("count").textContent = `${listing.nodes.length} of ${listing.total} nodes`;
+  $("more").hidden = listing.cursor == null;
+}
+
+// reload the pages loaded so far, so a refresh keeps what the user has scrolled to
+async function loadNodes() {
+  const wanted = Math.max(listing.nodes.length, PAGE_LIMIT);
+  let nodes = [];
+  let page = null;
+  do {
+    page = await fetchJson(listQuery(page ? page.nextCursor : null));
+    nodes = nodes.concat(page.nodes);
+  } while (page.nextCursor && nodes.length < wanted);
+  listing = { nodes, cursor: page.nextCursor ?? null, total: page.total };
+  renderListing();
+}
+
+async function loadMore() {
+  const page = await fetchJson(listQuery(listing.cursor));
+  listing = {
+    nodes: listing.nodes.concat(page.nodes),
+    cursor: page.nextCursor ?? null,
+    total: page.total,
+  };
+  renderListing();
+}
+
+async function refreshOverview() {
+  await Promise.all([loadSummary(), loadNodes()]);
+}
+
+// ---- node detail ----
+
+const SVG = "http://www.w3.org/2000/svg";
+
+// draw the series of values into the svg, scaled to `fixedMax` or to the largest value
+function lineChart(svg, series, fixedMax) {
+  const width = 600;
+  const height = 160;
+  const max = fixedMax ?? Math.max(1, ...series.flatMap((line) => line.values));
+  const label = document.createElementNS(SVG, "text");
+  label.setAttribute("x", "4");
+  label.setAttribute("y", "11");
+  label.textContent = fixedMax ? String(fixedMax) : max >= 1024 ? formatBytes(max) : max.toFixed(2);
+  const lines = series
+    .filter((line) => line.values.length > 1)
+    .map((line) => {
+      const points = line.values.map((value, index) => {
+        const x = (index / (line.values.length - 1)) * width;
+        const y = height - (value / max) * (height - 12);
+        return `${x.toFixed(1)},${y.toFixed(1)}`;
+      });
+      const polyline = document.createElementNS(SVG, "polyline");
+      polyline.setAttribute("points", points.join(" "));
+      if (line.class) {
+        polyline.setAttribute("class", line.class);
+      }
+      return polyline;
+    });
+  svg.replaceChildren(label, ...lines);
+}
+
+function virtualization(virtualization) {
+  switch (virtualization.kind) {
+    case "virtualMachine":
+      return `Virtual machine${virtualization.hypervisor ? ` (${virtualization.hypervisor})` : ""}`;
+    case "container":
+      return `Container${virtualization.runtime ? ` (${virtualization.runtime})` : ""}`;
+    default:
+      return "Bare metal";
+  }
+}
+
+function definition(term, value) {
+  return [element("dt", {}, term), element("dd", {}, value ?? "-")];
+}
+
+async function refreshDetail(ip) {
+  const node = await fetchJson(`/nodes/${encodeURIComponent(ip)}`);
+  const info = node.machineInfo;
+  // the records are the newest first
+  const records = node.usage.slice().reverse();
+  const usages = records.map((record) => record.machineUsage);
+  const latest = usages[usages.length - 1];
+
+  $("detail-title").textContent = info ? info.hostName : node.ip;
+  $("detail-subtitle").textContent =
+    `${node.ip} · ${node.state} · last updated ${formatTime(node.lastUpdated)}` +
+    (node.description ? ` · ${node.description}` : "");
+
+  lineChart($("chart-cpu"), [{ values: usages.map((usage) => averageCpu(usage) ?? 0) }], 100);
+  lineChart($("chart-memory"), [{ values: usages.map((usage) => memoryPercent(usage) ?? 0) }], 100);
+  lineChart($("chart-network"), [
+    { values: usages.map((usage) => usage.networkDown) },
+    { values: usages.map((usage) => usage.networkUp), class: "up" },
+  ]);
+  lineChart($("chart-load"), [{ values: usages.map((usage) => usage.loadAverage.one) }]);
+
+  const labels = Object.entries(node.labels)
+    .map(([key, value]) => `${key}=${value}`)
+    .join(", ");
+  $("machine").replaceChildren(
+    ...(info
+      ? [
+          ...definition("OS", `${info.os} ${info.osVersion}`),
+          ...definition("Kernel", info.kernelVersion),
+          ...definition("Architecture", info.arch),
+          ...definition("CPU", `${info.brand} (${info.numberOfCpu} cores, ${info.numberOfLogicalCpu} threads)`),
+          ...definition("Memory", formatBytes(info.totalMemory)),
+          ...definition("Virtualization", virtualization(info.virtualization)),
+        ]
+      : definition("Machine info", "not reported yet")),
+    ...definition("Labels", labels || "-"),
+    ...definition("Groups", node.groups.join(", ") || "-"),
+    ...definition("Uptime", latest ? `${Math.floor(latest.uptime / 3600)} h` : null),
+    ...definition("Processes", latest ? String(latest.processCount) : null),
+  );
+
+  $("filesystems").replaceChildren(
+    ...(latest ? latest.filesystems : []).map((filesystem) =>
+      element(
+        "tr",
+        {},
+        element("td", {}, filesystem.mountPoint),
+        element("td", {}, filesystem.device),
+        element("td", {}, filesystem.fileSystem),
+        element(
+          "td",
+          {},
+          bar(
+            filesystem.totalSpace > 0
+              ? ((filesystem.totalSpace - filesystem.availableSpace) / filesystem.totalSpace) * 100
+              : null,
+          ),
+        ),
+      ),
+    ),
+  );
+}
+
+// ---- routing ----
+
+function route() {
+  clearInterval(refreshTimer);
+  const match = location.hash.match(/^#\/nodes\/([^/]+)$/);
+  $("overview").hidden = match != null;
+  $("detail").hidden = match == null;
+  const refresh = match ? () => refreshDetail(decodeURIComponent(match[1])) : refreshOverview;
+  const run = () =>
+    refresh()
+      .then(() => {
+        showError(null);
+        markUpdated();
+      })
+      .catch(showError);
+  run();
+  refreshTimer = setInterval(run, REFRESH_INTERVAL);
+}
+
+$("filters").addEventListener("submit", (event) => {
+  event.preventDefault();
+  listing = { nodes: [], cursor: null, total: 0 };
+  route();
+});
+$("more").addEventListener("click", () => loadMore().catch(showError));
+window.addEventListener("hashchange", route);
+route();